@@ -0,0 +1,322 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Command-line tool for working with git stubs.
+
+use anyhow::{Context, Result};
+use atomicwrites::{AtomicFile, OverwriteBehavior};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{Parser, Subcommand};
+use fs_err as fs;
+use git_stub::GitCommitHash;
+use git_stub_vcs::{CommitMap, FilterProcess, GitStubIndex, Vcs};
+use std::{
+    io::{self, Write},
+    process::ExitCode,
+};
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct App {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run as a Git long-running filter process.
+    ///
+    /// Expands git stubs on checkout and collapses them back on add. To use
+    /// this, run `git config filter.gitstub.process "git-stub filter-process"`
+    /// and mark files with `filter=gitstub` in `.gitattributes`.
+    FilterProcess,
+
+    /// Print the contents referenced by a git stub file.
+    ///
+    /// For use as a Git diff driver, so that `git diff` shows changes to the
+    /// referenced contents. To use this, run
+    /// `git config diff.gitstub.textconv "git-stub textconv"` and mark git
+    /// stubs with `diff=gitstub` in `.gitattributes`.
+    Textconv {
+        /// The git stub file.
+        path: Utf8PathBuf,
+    },
+
+    /// Check git stubs in a repository for common mistakes.
+    ///
+    /// Reports git stubs whose referenced contents are identical to a tracked
+    /// file and, with `--protected-ref`, git stubs whose commits are not
+    /// reachable from a protected branch. Exits with a non-zero status if any
+    /// problems are found.
+    Check {
+        /// The repository root.
+        #[arg(long, default_value = ".")]
+        repo_root: Utf8PathBuf,
+
+        /// Require git stub commits to be reachable from this revision
+        /// (typically the main branch).
+        #[arg(long, value_name = "REV")]
+        protected_ref: Option<String>,
+    },
+
+    /// Re-pin git stubs whose commits are not reachable from a protected
+    /// branch.
+    ///
+    /// For use after a squash merge or a history rewrite. Each such git stub
+    /// is rewritten to point at an equivalent commit reachable from
+    /// `--protected-ref`: either the commit it maps to in `--commit-map`, or
+    /// the oldest commit in which the referenced file has identical
+    /// contents. Exits with a non-zero status if any git stubs couldn't be
+    /// re-pinned.
+    Repin {
+        /// The repository root.
+        #[arg(long, default_value = ".")]
+        repo_root: Utf8PathBuf,
+
+        /// The revision that git stub commits should be reachable from
+        /// (typically the main branch).
+        #[arg(long, value_name = "REV")]
+        protected_ref: String,
+
+        /// A commit map written by `git filter-repo`, used to translate old
+        /// commit hashes to new ones.
+        #[arg(long, value_name = "PATH")]
+        commit_map: Option<Utf8PathBuf>,
+
+        /// Print the changes that would be made without writing them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Protect commits referenced by git stubs from garbage collection.
+    ///
+    /// Creates a `refs/git-stub/keep/<commit>` ref for every commit
+    /// referenced by a git stub, and deletes keep-alive refs for commits that
+    /// are no longer referenced. Exits with a non-zero status if any
+    /// referenced commits don't exist.
+    KeepRefs {
+        /// The repository root.
+        #[arg(long, default_value = ".")]
+        repo_root: Utf8PathBuf,
+    },
+
+    /// List git stubs that reference a commit or path.
+    ///
+    /// For use before rewriting history or deleting a branch, to find out
+    /// which git stubs would break. Prints `<git stub path>: <git stub>` for
+    /// each git stub that references any of the given commits or paths, or
+    /// for all git stubs if none are given.
+    Find {
+        /// The repository root.
+        #[arg(long, default_value = ".")]
+        repo_root: Utf8PathBuf,
+
+        /// Find git stubs at this revision rather than in the working tree.
+        #[arg(long, value_name = "REV")]
+        revision: Option<String>,
+
+        /// Find git stubs that reference this commit.
+        #[arg(long)]
+        commit: Vec<GitCommitHash>,
+
+        /// Find git stubs that reference this path, relative to the
+        /// repository root.
+        #[arg(long)]
+        path: Vec<Utf8PathBuf>,
+    },
+}
+
+fn main() -> Result<ExitCode> {
+    let app = App::parse();
+    match app.command {
+        Command::FilterProcess => {
+            let filter = FilterProcess::new(".")?;
+            // Git shows the filter's standard error to the user.
+            filter.run(io::stdin().lock(), io::stdout().lock(), |error| {
+                eprintln!("git-stub: {:#}", anyhow::Error::from(error));
+            })?;
+        }
+        Command::Textconv { path } => {
+            let contents = fs::read(&path)?;
+            // Git runs diff drivers from the top of the working tree, with
+            // an environment that may select the repository.
+            let converted = git_stub_vcs::textconv(
+                &Vcs::git()?.with_env_isolation(false),
+                Utf8Path::new("."),
+                &contents,
+            )?;
+            io::stdout().lock().write_all(&converted)?;
+        }
+        Command::Check { repo_root, protected_ref } => {
+            return check(&repo_root, protected_ref.as_deref());
+        }
+        Command::Repin { repo_root, protected_ref, commit_map, dry_run } => {
+            return repin(
+                &repo_root,
+                &protected_ref,
+                commit_map.as_deref(),
+                dry_run,
+            );
+        }
+        Command::KeepRefs { repo_root } => return keep_refs(&repo_root),
+        Command::Find { repo_root, revision, commit, path } => {
+            find(&repo_root, revision.as_deref(), &commit, &path)?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs all checks, printing any problems found.
+fn check(
+    repo_root: &Utf8Path,
+    protected_ref: Option<&str>,
+) -> Result<ExitCode> {
+    let vcs = Vcs::git()?;
+    let mut problems = 0;
+
+    for duplicate in vcs.find_duplicate_contents(repo_root)? {
+        problems += 1;
+        let tracked_paths: Vec<_> = duplicate
+            .tracked_paths()
+            .iter()
+            .map(|path| path.as_str())
+            .collect();
+        println!(
+            "{}: referenced contents are identical to tracked file {}",
+            duplicate.stub_file().path(),
+            tracked_paths.join(", "),
+        );
+    }
+
+    if let Some(protected_ref) = protected_ref {
+        for unreachable in
+            vcs.find_unreachable_commits(repo_root, protected_ref)?
+        {
+            problems += 1;
+            let stub_file = unreachable.stub_file();
+            let commit = stub_file.stub().commit();
+            if unreachable.commit_exists() {
+                println!(
+                    "{}: commit {commit} is not reachable from {protected_ref}",
+                    stub_file.path(),
+                );
+            } else {
+                println!(
+                    "{}: commit {commit} does not exist",
+                    stub_file.path()
+                );
+            }
+        }
+    }
+
+    if problems == 0 {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        eprintln!("error: found {problems} problem(s) with git stubs");
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+/// Re-pins git stubs with unreachable commits, printing each change.
+fn repin(
+    repo_root: &Utf8Path,
+    protected_ref: &str,
+    commit_map: Option<&Utf8Path>,
+    dry_run: bool,
+) -> Result<ExitCode> {
+    let vcs = Vcs::git()?;
+    let commit_map = commit_map
+        .map(|path| -> Result<CommitMap> {
+            let contents = fs::read_to_string(path)?;
+            contents
+                .parse()
+                .with_context(|| format!("failed to parse commit map {path}"))
+        })
+        .transpose()?;
+
+    let mut unresolved = 0;
+    for repin in
+        vcs.repin_git_stubs(repo_root, protected_ref, commit_map.as_ref())?
+    {
+        let stub_file = repin.stub_file();
+        let old_commit = stub_file.stub().commit();
+        let Some(new_stub) = repin.new_stub() else {
+            unresolved += 1;
+            println!(
+                "{}: no equivalent commit found for {old_commit}",
+                stub_file.path(),
+            );
+            continue;
+        };
+        println!("{}: {old_commit} -> {}", stub_file.path(), new_stub.commit());
+        if !dry_run {
+            let path = repo_root.join(stub_file.path());
+            AtomicFile::new(&path, OverwriteBehavior::AllowOverwrite)
+                .write(|f| f.write_all(new_stub.to_file_contents().as_bytes()))
+                .with_context(|| format!("failed to write {path}"))?;
+        }
+    }
+
+    if unresolved == 0 {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        eprintln!("error: failed to re-pin {unresolved} git stub(s)");
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+/// Updates keep-alive refs, printing each change.
+fn keep_refs(repo_root: &Utf8Path) -> Result<ExitCode> {
+    let update = Vcs::git()?.update_keep_refs(repo_root)?;
+    for name in update.created() {
+        println!("created {name}");
+    }
+    for name in update.deleted() {
+        println!("deleted {name}");
+    }
+    for stub_file in update.missing() {
+        println!(
+            "{}: commit {} does not exist",
+            stub_file.path(),
+            stub_file.stub().commit(),
+        );
+    }
+
+    if update.missing().is_empty() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        eprintln!(
+            "error: {} git stub(s) reference commits that do not exist",
+            update.missing().len(),
+        );
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+/// Prints git stubs that reference any of `commits` or `paths`.
+fn find(
+    repo_root: &Utf8Path,
+    revision: Option<&str>,
+    commits: &[GitCommitHash],
+    paths: &[Utf8PathBuf],
+) -> Result<()> {
+    let vcs = Vcs::detect(repo_root)?;
+    let stub_files = match revision {
+        Some(revision) => vcs.find_git_stubs_at(repo_root, revision)?,
+        None => vcs.find_git_stubs(repo_root)?,
+    };
+    let index = GitStubIndex::new(stub_files);
+
+    let mut matches: Vec<_> = if commits.is_empty() && paths.is_empty() {
+        index.stub_files().iter().collect()
+    } else {
+        let by_commit = commits.iter().flat_map(|&c| index.by_commit(c));
+        let by_path = paths.iter().flat_map(|p| index.by_path(p));
+        by_commit.chain(by_path).collect()
+    };
+    matches.sort_by_key(|stub_file| stub_file.path());
+    matches.dedup_by_key(|stub_file| stub_file.path());
+    for stub_file in matches {
+        println!("{}: {}", stub_file.path(), stub_file.stub());
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Tests for `git-stub check`.
+
+use crate::helpers::{GIT_STUB_BIN, run_git, setup_git_repo, write_file};
+use anyhow::Result;
+use std::process::Command;
+
+#[test]
+fn test_check_duplicate_contents() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo("{}")?;
+    let repo_root = temp.path();
+
+    let output = Command::new(GIT_STUB_BIN)
+        .arg("check")
+        .current_dir(repo_root)
+        .output()?;
+    assert!(output.status.success(), "no stubs should pass");
+
+    write_file(
+        repo_root.join("openapi/api-v1.json.gitstub"),
+        format!("{commit_hash}:openapi/api.json\n"),
+    )?;
+    run_git(repo_root, &["add", "."])?;
+    let output = Command::new(GIT_STUB_BIN)
+        .args(["check", "--repo-root", repo_root.as_str()])
+        .output()?;
+    assert!(!output.status.success(), "duplicate should fail the check");
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        "openapi/api-v1.json.gitstub: referenced contents are identical to \
+         tracked file openapi/api.json\n",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_check_protected_ref() -> Result<()> {
+    let (temp, first) = setup_git_repo("{}")?;
+    let repo_root = temp.path();
+    run_git(repo_root, &["branch", "protected"])?;
+    write_file(repo_root.join("openapi/api.json"), "{\"version\": 2}")?;
+    run_git(repo_root, &["commit", "-am", "Update API spec"])?;
+    let second = run_git(repo_root, &["rev-parse", "HEAD"])?;
+    write_file(repo_root.join("openapi/api.json"), "{\"version\": 3}")?;
+    run_git(repo_root, &["commit", "-am", "Update API spec again"])?;
+
+    write_file(
+        repo_root.join("openapi/api-v1.json.gitstub"),
+        format!("{first}:openapi/api.json\n"),
+    )?;
+    let output = Command::new(GIT_STUB_BIN)
+        .args(["check", "--protected-ref", "protected"])
+        .current_dir(repo_root)
+        .output()?;
+    assert!(output.status.success(), "reachable commit should pass");
+
+    write_file(
+        repo_root.join("openapi/api-v2.json.gitstub"),
+        format!("{second}:openapi/api.json\n"),
+    )?;
+    let output = Command::new(GIT_STUB_BIN)
+        .args(["check", "--protected-ref", "protected"])
+        .current_dir(repo_root)
+        .output()?;
+    assert!(!output.status.success(), "unreachable commit should fail");
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        format!(
+            "openapi/api-v2.json.gitstub: commit {second} is not reachable \
+             from protected\n"
+        ),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Tests for `git-stub filter-process`, driven by Git itself.
+
+use crate::helpers::{
+    GIT_STUB_BIN, run_git, setup_git_repo, try_run_git, write_file,
+};
+use anyhow::Result;
+use std::fs;
+
+const CONTENTS: &str = r#"{"name": "test-api", "version": "1.0.0"}"#;
+
+#[test]
+fn test_filter_process_checkout_and_add() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo(CONTENTS)?;
+    let repo_root = temp.path();
+
+    let process = format!("'{GIT_STUB_BIN}' filter-process");
+    run_git(repo_root, &["config", "filter.gitstub.process", &process])?;
+    run_git(repo_root, &["config", "filter.gitstub.required", "true"])?;
+    write_file(
+        repo_root.join(".gitattributes"),
+        "openapi/api-v1.json filter=gitstub\n",
+    )?;
+
+    // Adding stub text stores it as-is.
+    let stub_text = format!("{commit_hash}:openapi/api.json\n");
+    let stub_path = repo_root.join("openapi/api-v1.json");
+    write_file(&stub_path, &stub_text)?;
+    run_git(repo_root, &["add", "."])?;
+    run_git(repo_root, &["commit", "-m", "Add stub"])?;
+    assert_eq!(
+        run_git(repo_root, &["cat-file", "blob", "HEAD:openapi/api-v1.json"])?,
+        stub_text.trim(),
+        "repository should store the stub"
+    );
+
+    // Checking out expands the stub.
+    fs::remove_file(&stub_path)?;
+    run_git(repo_root, &["checkout", "--", "openapi/api-v1.json"])?;
+    assert_eq!(
+        fs::read_to_string(&stub_path)?,
+        CONTENTS,
+        "working tree should have the referenced contents"
+    );
+
+    // The expanded file cleans back to the stub, so it isn't modified.
+    run_git(repo_root, &["add", "--renormalize", "."])?;
+    assert_eq!(
+        run_git(repo_root, &["status", "--porcelain"])?,
+        "",
+        "expanded file should not show as modified"
+    );
+
+    // Modifying an expanded file is an error.
+    write_file(&stub_path, "{}")?;
+    let output = try_run_git(repo_root, &["add", "openapi/api-v1.json"])?;
+    assert!(!output.status.success(), "adding modified contents should fail");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("differs from"),
+        "stderr should explain the failure: {stderr}"
+    );
+
+    Ok(())
+}
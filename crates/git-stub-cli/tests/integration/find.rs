@@ -0,0 +1,93 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Tests for `git-stub find`.
+
+use crate::helpers::{GIT_STUB_BIN, run_git, setup_git_repo, write_file};
+use anyhow::Result;
+use std::{fs, process::Command};
+
+#[test]
+fn test_find() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo("{}")?;
+    let repo_root = temp.path();
+    let api_stub = format!("{commit_hash}:openapi/api.json");
+    let readme_stub = format!("{}:README.md", "ab".repeat(20));
+    write_file(
+        repo_root.join("openapi/api-v1.json.gitstub"),
+        format!("{api_stub}\n"),
+    )?;
+    write_file(
+        repo_root.join("openapi/readme.md.gitstub"),
+        format!("{readme_stub}\n"),
+    )?;
+    run_git(repo_root, &["add", "."])?;
+    run_git(repo_root, &["commit", "-m", "Add stubs"])?;
+    fs::remove_file(repo_root.join("openapi/api-v1.json.gitstub"))?;
+
+    let find = |args: &[&str]| -> Result<String> {
+        let output = Command::new(GIT_STUB_BIN)
+            .arg("find")
+            .args(args)
+            .current_dir(repo_root)
+            .output()?;
+        assert!(output.status.success(), "find should succeed");
+        Ok(String::from_utf8(output.stdout)?)
+    };
+
+    assert_eq!(
+        find(&[])?,
+        format!("openapi/readme.md.gitstub: {readme_stub}\n")
+    );
+    assert_eq!(find(&["--commit", &commit_hash])?, "");
+    assert_eq!(
+        find(&["--revision", "HEAD", "--commit", &commit_hash])?,
+        format!("openapi/api-v1.json.gitstub: {api_stub}\n"),
+    );
+    assert_eq!(
+        find(&[
+            "--revision",
+            "HEAD",
+            "--path",
+            "README.md",
+            "--commit",
+            &commit_hash
+        ])?,
+        format!(
+            "openapi/api-v1.json.gitstub: {api_stub}\n\
+             openapi/readme.md.gitstub: {readme_stub}\n"
+        ),
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_find_ignores_inherited_git_env() -> Result<()> {
+    // Git sets GIT_DIR and GIT_INDEX_FILE when running hooks. Commands run
+    // from a hook in another repository should still read `--repo-root`.
+    let (temp, commit_hash) = setup_git_repo("{}")?;
+    let repo_root = temp.path();
+    let stub = format!("{commit_hash}:openapi/api.json");
+    write_file(repo_root.join("api.json.gitstub"), format!("{stub}\n"))?;
+    run_git(repo_root, &["add", "."])?;
+    run_git(repo_root, &["commit", "-m", "Add stub"])?;
+
+    let (other, _) = setup_git_repo("[]")?;
+    let output = Command::new(GIT_STUB_BIN)
+        .args(["find", "--revision", "HEAD"])
+        .current_dir(repo_root)
+        .env("GIT_DIR", other.path().join(".git"))
+        .env("GIT_INDEX_FILE", other.path().join(".git/index"))
+        .output()?;
+    assert!(
+        output.status.success(),
+        "find should succeed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        format!("api.json.gitstub: {stub}\n"),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Shared helpers for integration tests.
+
+use anyhow::Result;
+use atomicwrites::{AtomicFile, OverwriteBehavior};
+use camino::Utf8Path;
+use camino_tempfile::Utf8TempDir;
+use std::{
+    io::Write,
+    process::{Command, Output},
+};
+
+/// The path to the `git-stub` binary under test.
+pub(crate) const GIT_STUB_BIN: &str = env!("CARGO_BIN_EXE_git-stub");
+
+/// Returns a `Command` for git, respecting the `$GIT` environment variable.
+pub(crate) fn git_command() -> Command {
+    let bin = std::env::var("GIT").unwrap_or_else(|_| "git".to_string());
+    Command::new(bin)
+}
+
+/// Runs git with `args` in `repo_root`, returning its output regardless of
+/// whether it succeeded.
+pub(crate) fn try_run_git(
+    repo_root: &Utf8Path,
+    args: &[&str],
+) -> Result<Output> {
+    Ok(git_command().args(args).current_dir(repo_root).output()?)
+}
+
+/// Runs git with `args` in `repo_root`, returning its trimmed stdout.
+pub(crate) fn run_git(repo_root: &Utf8Path, args: &[&str]) -> Result<String> {
+    let output = try_run_git(repo_root, args)?;
+    assert!(
+        output.status.success(),
+        "git {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// Writes content to a file atomically.
+pub(crate) fn write_file(
+    path: impl AsRef<Utf8Path>,
+    content: impl AsRef<[u8]>,
+) -> std::io::Result<()> {
+    let path = path.as_ref();
+    AtomicFile::new(path, OverwriteBehavior::AllowOverwrite)
+        .write(|f| f.write_all(content.as_ref()))
+        .map_err(|e| e.into())
+}
+
+/// Sets up a temporary git repository with `openapi/api.json` committed
+/// with `contents`. Returns (temp_dir, commit_hash).
+pub(crate) fn setup_git_repo(contents: &str) -> Result<(Utf8TempDir, String)> {
+    let temp = Utf8TempDir::with_prefix("git-stub-cli-")?;
+    let repo_root = temp.path();
+
+    run_git(repo_root, &["init"])?;
+    run_git(repo_root, &["config", "user.email", "test@example.com"])?;
+    run_git(repo_root, &["config", "user.name", "Test User"])?;
+
+    std::fs::create_dir_all(repo_root.join("openapi"))?;
+    write_file(repo_root.join("openapi/api.json"), contents)?;
+    run_git(repo_root, &["add", "."])?;
+    run_git(repo_root, &["commit", "-m", "Add API spec"])?;
+    let commit_hash = run_git(repo_root, &["rev-parse", "HEAD"])?;
+
+    Ok((temp, commit_hash))
+}
@@ -0,0 +1,39 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Tests for `git-stub keep-refs`.
+
+use crate::helpers::{GIT_STUB_BIN, run_git, setup_git_repo, write_file};
+use anyhow::Result;
+use std::{fs, process::Command};
+
+#[test]
+fn test_keep_refs() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo("{}")?;
+    let repo_root = temp.path();
+    let stub_path = repo_root.join("openapi/api-v1.json.gitstub");
+    write_file(&stub_path, format!("{commit_hash}:openapi/api.json\n"))?;
+
+    let output = Command::new(GIT_STUB_BIN)
+        .arg("keep-refs")
+        .current_dir(repo_root)
+        .output()?;
+    assert!(output.status.success(), "keep-refs should succeed");
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        format!("created refs/git-stub/keep/{commit_hash}\n"),
+    );
+
+    fs::remove_file(&stub_path)?;
+    let output = Command::new(GIT_STUB_BIN)
+        .args(["keep-refs", "--repo-root", repo_root.as_str()])
+        .output()?;
+    assert!(output.status.success(), "keep-refs should succeed");
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        format!("deleted refs/git-stub/keep/{commit_hash}\n"),
+    );
+    let refs = run_git(repo_root, &["for-each-ref", "refs/git-stub/"])?;
+    assert_eq!(refs, "", "keep-alive ref should be deleted");
+
+    Ok(())
+}
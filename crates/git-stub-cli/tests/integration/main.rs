@@ -0,0 +1,9 @@
+// Copyright 2026 Oxide Computer Company
+
+mod check;
+mod filter;
+mod find;
+mod helpers;
+mod keep_refs;
+mod repin;
+mod textconv;
@@ -0,0 +1,71 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Tests for `git-stub repin`.
+
+use crate::helpers::{GIT_STUB_BIN, run_git, setup_git_repo, write_file};
+use anyhow::Result;
+use std::{fs, process::Command};
+
+#[test]
+fn test_repin() -> Result<()> {
+    let (temp, _) = setup_git_repo("{}")?;
+    let repo_root = temp.path();
+
+    run_git(repo_root, &["checkout", "-b", "feature"])?;
+    write_file(repo_root.join("openapi/api.json"), "{\"version\": 2}")?;
+    run_git(repo_root, &["commit", "-am", "Update API spec"])?;
+    let feature = run_git(repo_root, &["rev-parse", "HEAD"])?;
+    run_git(repo_root, &["checkout", "-"])?;
+    write_file(repo_root.join("openapi/api.json"), "{\"version\": 2}")?;
+    run_git(repo_root, &["commit", "-am", "Squash merge feature"])?;
+    let squashed = run_git(repo_root, &["rev-parse", "HEAD"])?;
+
+    let stub_path = repo_root.join("openapi/api-v2.json.gitstub");
+    let old_stub = format!("{feature}:openapi/api.json\n");
+    write_file(&stub_path, &old_stub)?;
+
+    let output = Command::new(GIT_STUB_BIN)
+        .args(["repin", "--protected-ref", "HEAD", "--dry-run"])
+        .current_dir(repo_root)
+        .output()?;
+    assert!(output.status.success(), "repin should succeed");
+    let expected =
+        format!("openapi/api-v2.json.gitstub: {feature} -> {squashed}\n");
+    assert_eq!(String::from_utf8(output.stdout)?, expected);
+    assert_eq!(fs::read_to_string(&stub_path)?, old_stub, "dry run");
+
+    let output = Command::new(GIT_STUB_BIN)
+        .args(["repin", "--protected-ref", "HEAD"])
+        .current_dir(repo_root)
+        .output()?;
+    assert!(output.status.success(), "repin should succeed");
+    assert_eq!(String::from_utf8(output.stdout)?, expected);
+    assert_eq!(
+        fs::read_to_string(&stub_path)?,
+        format!("{squashed}:openapi/api.json\n"),
+    );
+
+    // A commit that doesn't exist can't be re-pinned without a commit map.
+    let missing = "dead".repeat(10);
+    write_file(&stub_path, format!("{missing}:openapi/api.json\n"))?;
+    let output = Command::new(GIT_STUB_BIN)
+        .args(["repin", "--protected-ref", "HEAD"])
+        .current_dir(repo_root)
+        .output()?;
+    assert!(!output.status.success(), "missing commit should fail");
+
+    let commit_map = repo_root.join("commit-map");
+    write_file(&commit_map, format!("old new\n{missing} {squashed}\n"))?;
+    let output = Command::new(GIT_STUB_BIN)
+        .args(["repin", "--protected-ref", "HEAD", "--commit-map"])
+        .arg(commit_map.as_str())
+        .current_dir(repo_root)
+        .output()?;
+    assert!(output.status.success(), "commit map should be used");
+    assert_eq!(
+        fs::read_to_string(&stub_path)?,
+        format!("{squashed}:openapi/api.json\n"),
+    );
+
+    Ok(())
+}
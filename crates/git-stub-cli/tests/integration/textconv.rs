@@ -0,0 +1,51 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Tests for `git-stub textconv`, driven by `git diff`.
+
+use crate::helpers::{GIT_STUB_BIN, run_git, setup_git_repo, write_file};
+use anyhow::Result;
+
+#[test]
+fn test_textconv_git_diff() -> Result<()> {
+    let (temp, first_commit) =
+        setup_git_repo("{\n  \"version\": \"1.0.0\"\n}\n")?;
+    let repo_root = temp.path();
+
+    write_file(
+        repo_root.join("openapi/api.json"),
+        "{\n  \"version\": \"2.0.0\"\n}\n",
+    )?;
+    run_git(repo_root, &["commit", "-am", "Bump version"])?;
+    let second_commit = run_git(repo_root, &["rev-parse", "HEAD"])?;
+
+    let textconv = format!("'{GIT_STUB_BIN}' textconv");
+    run_git(repo_root, &["config", "diff.gitstub.textconv", &textconv])?;
+    write_file(repo_root.join(".gitattributes"), "*.gitstub diff=gitstub\n")?;
+    let stub_path = repo_root.join("openapi/api.json.gitstub");
+    write_file(&stub_path, format!("{first_commit}:openapi/api.json\n"))?;
+    run_git(repo_root, &["add", "."])?;
+    run_git(repo_root, &["commit", "-m", "Add stub"])?;
+
+    write_file(&stub_path, format!("{second_commit}:openapi/api.json\n"))?;
+    let diff = run_git(repo_root, &["diff", "openapi/api.json.gitstub"])?;
+    assert!(
+        diff.contains("-  \"version\": \"1.0.0\"")
+            && diff.contains("+  \"version\": \"2.0.0\""),
+        "diff should show the referenced contents:\n{diff}"
+    );
+    assert!(
+        !diff.contains(&second_commit),
+        "diff should not show the commit hashes:\n{diff}"
+    );
+
+    let diff = run_git(
+        repo_root,
+        &["diff", "--no-textconv", "openapi/api.json.gitstub"],
+    )?;
+    assert!(
+        diff.contains(&format!("+{second_commit}:openapi/api.json")),
+        "--no-textconv should show the stubs:\n{diff}"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Cancellation of VCS operations.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// A token that cancels VCS operations when triggered.
+///
+/// Clones share the same state, so a clone can be passed to
+/// [`Vcs::with_cancellation`](crate::Vcs::with_cancellation) and another
+/// kept to call [`cancel`](Self::cancel) from a different thread. Once
+/// cancelled, running VCS processes are killed, and operations return a
+/// `Cancelled` error (e.g. [`ReadContentsError::Cancelled`]).
+///
+/// [`ReadContentsError::Cancelled`]: crate::ReadContentsError::Cancelled
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    /// Wakes asynchronous VCS commands waiting on the token.
+    #[cfg(feature = "tokio")]
+    notify: tokio::sync::Notify,
+}
+
+impl CancellationToken {
+    /// Creates a new token that has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels operations using this token (or any of its clones).
+    ///
+    /// Cancellation can't be undone.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        #[cfg(feature = "tokio")]
+        self.inner.notify.notify_waiters();
+    }
+
+    /// Returns whether [`cancel`](Self::cancel) has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Waits until [`cancel`](Self::cancel) has been called.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn cancelled(&self) {
+        let notified = self.inner.notify.notified();
+        let mut notified = std::pin::pin!(notified);
+        // Register for notifications before checking the flag, so that a
+        // concurrent `cancel` isn't missed.
+        notified.as_mut().enable();
+        if !self.is_cancelled() {
+            notified.await;
+        }
+    }
+}
+
+impl PartialEq for CancellationToken {
+    /// Two tokens are equal if they share the same state.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl Eq for CancellationToken {}
@@ -0,0 +1,255 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Following chains of git stubs that reference other git stubs.
+
+use crate::{ReadContentsError, ResolveChainError, Vcs};
+use camino::Utf8Path;
+use git_stub::GitStub;
+use std::collections::BTreeSet;
+
+/// The default maximum depth for [`Vcs::read_git_stub_chain`].
+pub const DEFAULT_MAX_CHAIN_DEPTH: usize = 8;
+
+/// The result of following a chain of git stubs.
+///
+/// Returned by [`Vcs::read_git_stub_chain`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GitStubChain {
+    stubs: Vec<GitStub>,
+    contents: Vec<u8>,
+}
+
+impl GitStubChain {
+    /// Returns the git stubs in the chain, starting with the one that was
+    /// read. The last git stub references the final contents.
+    pub fn stubs(&self) -> &[GitStub] {
+        &self.stubs
+    }
+
+    /// Returns the final contents.
+    pub fn contents(&self) -> &[u8] {
+        &self.contents
+    }
+
+    /// Consumes `self`, returning the final contents.
+    pub fn into_contents(self) -> Vec<u8> {
+        self.contents
+    }
+}
+
+impl Vcs {
+    /// Reads the contents referenced by a git stub, following chains of git
+    /// stubs.
+    ///
+    /// If the file referenced by `stub` is itself a git stub (its path has a
+    /// `.gitstub` extension), that git stub is read and followed in turn.
+    /// This happens when a directory that already contained git stubs is
+    /// archived.
+    ///
+    /// `max_depth` is the maximum number of additional git stubs to follow
+    /// (see [`DEFAULT_MAX_CHAIN_DEPTH`]). With a `max_depth` of 0, a git stub
+    /// that references another git stub is an error.
+    ///
+    /// Errors include the full chain of git stubs followed so far.
+    pub fn read_git_stub_chain(
+        &self,
+        stub: &GitStub,
+        repo_root: &Utf8Path,
+        max_depth: usize,
+    ) -> Result<GitStubChain, ResolveChainError> {
+        follow_chain(stub, max_depth, |stub| {
+            self.read_git_stub_contents(stub, repo_root)
+        })
+    }
+
+    /// Asynchronously reads the contents referenced by a git stub, following
+    /// chains of git stubs.
+    ///
+    /// This is the asynchronous version of [`Vcs::read_git_stub_chain`].
+    #[cfg(feature = "tokio")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "tokio")))]
+    pub async fn read_git_stub_chain_async(
+        &self,
+        stub: &GitStub,
+        repo_root: &Utf8Path,
+        max_depth: usize,
+    ) -> Result<GitStubChain, ResolveChainError> {
+        let mut state = ChainState::new(stub, max_depth);
+        loop {
+            let contents = self
+                .read_git_stub_contents_async(state.current(), repo_root)
+                .await;
+            if let Some(chain) = state.advance(contents)? {
+                return Ok(chain);
+            }
+        }
+    }
+}
+
+fn follow_chain(
+    stub: &GitStub,
+    max_depth: usize,
+    mut read: impl FnMut(&GitStub) -> Result<Vec<u8>, ReadContentsError>,
+) -> Result<GitStubChain, ResolveChainError> {
+    let mut state = ChainState::new(stub, max_depth);
+    loop {
+        let contents = read(state.current());
+        if let Some(chain) = state.advance(contents)? {
+            return Ok(chain);
+        }
+    }
+}
+
+/// The state of following a chain of git stubs, shared between the
+/// synchronous and asynchronous implementations.
+struct ChainState {
+    stubs: Vec<GitStub>,
+    seen: BTreeSet<String>,
+    max_depth: usize,
+}
+
+impl ChainState {
+    fn new(stub: &GitStub, max_depth: usize) -> Self {
+        let mut seen = BTreeSet::new();
+        seen.insert(stub.to_string());
+        ChainState { stubs: vec![stub.clone()], seen, max_depth }
+    }
+
+    /// Returns the git stub to read next.
+    fn current(&self) -> &GitStub {
+        self.stubs.last().expect("chain is non-empty")
+    }
+
+    /// Handles the result of reading the current git stub, returning the
+    /// chain if it is complete.
+    fn advance(
+        &mut self,
+        contents: Result<Vec<u8>, ReadContentsError>,
+    ) -> Result<Option<GitStubChain>, ResolveChainError> {
+        let stubs = &mut self.stubs;
+        let contents = match contents {
+            Ok(contents) => contents,
+            Err(error) => {
+                return Err(ResolveChainError::ReadContents {
+                    chain: std::mem::take(stubs),
+                    error: Box::new(error),
+                });
+            }
+        };
+        let current = stubs.last().expect("chain is non-empty");
+        if current.path().extension() != Some("gitstub") {
+            let stubs = std::mem::take(stubs);
+            return Ok(Some(GitStubChain { stubs, contents }));
+        }
+
+        let next: GitStub = match String::from_utf8_lossy(&contents).parse() {
+            Ok(next) => next,
+            Err(error) => {
+                return Err(ResolveChainError::InvalidGitStub {
+                    chain: std::mem::take(stubs),
+                    error,
+                });
+            }
+        };
+        let is_new = self.seen.insert(next.to_string());
+        stubs.push(next);
+        if !is_new {
+            return Err(ResolveChainError::Cycle {
+                chain: std::mem::take(stubs),
+            });
+        }
+        if stubs.len() > self.max_depth + 1 {
+            return Err(ResolveChainError::TooDeep {
+                chain: std::mem::take(stubs),
+                max_depth: self.max_depth,
+            });
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn stub(commit_byte: &str, path: &str) -> GitStub {
+        format!("{}:{path}", commit_byte.repeat(20)).parse().unwrap()
+    }
+
+    /// Follows a chain using an in-memory map from git stub to contents.
+    fn follow(
+        start: &GitStub,
+        max_depth: usize,
+        files: &[(&GitStub, String)],
+    ) -> Result<GitStubChain, ResolveChainError> {
+        let files: BTreeMap<_, _> = files
+            .iter()
+            .map(|(stub, contents)| (stub.to_string(), contents.clone()))
+            .collect();
+        follow_chain(start, max_depth, |stub| {
+            Ok(files[&stub.to_string()].clone().into_bytes())
+        })
+    }
+
+    #[test]
+    fn test_follow_chain() {
+        let a = stub("aa", "archive/api.json.gitstub");
+        let b = stub("bb", "api.json");
+        let files = [(&a, b.to_file_contents()), (&b, "{}".to_owned())];
+
+        let chain = follow(&a, DEFAULT_MAX_CHAIN_DEPTH, &files).unwrap();
+        assert_eq!(chain.stubs(), [a.clone(), b.clone()]);
+        assert_eq!(chain.contents(), b"{}");
+
+        let chain = follow(&b, 0, &files).unwrap();
+        assert_eq!(chain.stubs(), std::slice::from_ref(&b), "non-chained");
+
+        let error = follow(&a, 0, &files).unwrap_err();
+        assert!(
+            matches!(
+                &error,
+                ResolveChainError::TooDeep { chain, max_depth: 0 }
+                    if chain == &[a.clone(), b.clone()]
+            ),
+            "unexpected error: {error:?}"
+        );
+        assert_eq!(
+            error.to_string(),
+            format!("git stub chain exceeds maximum depth 0: {a} -> {b}"),
+        );
+    }
+
+    #[test]
+    fn test_follow_chain_cycle() {
+        let a = stub("aa", "a.json.gitstub");
+        let b = stub("bb", "b.json.gitstub");
+        let files = [(&a, b.to_file_contents()), (&b, a.to_file_contents())];
+
+        let error = follow(&a, DEFAULT_MAX_CHAIN_DEPTH, &files).unwrap_err();
+        assert!(
+            matches!(
+                &error,
+                ResolveChainError::Cycle { chain }
+                    if chain == &[a.clone(), b.clone(), a.clone()]
+            ),
+            "unexpected error: {error:?}"
+        );
+    }
+
+    #[test]
+    fn test_follow_chain_invalid() {
+        let a = stub("aa", "a.json.gitstub");
+        let files = [(&a, "not a stub".to_owned())];
+
+        let error = follow(&a, DEFAULT_MAX_CHAIN_DEPTH, &files).unwrap_err();
+        assert!(
+            matches!(
+                &error,
+                ResolveChainError::InvalidGitStub { chain, .. }
+                    if chain == std::slice::from_ref(&a)
+            ),
+            "unexpected error: {error:?}"
+        );
+    }
+}
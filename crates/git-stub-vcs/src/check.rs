@@ -0,0 +1,188 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Checks for common mistakes with git stubs.
+
+use crate::{CheckError, GitStubFile, Vcs, scan::split_output};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::collections::BTreeMap;
+
+/// A git stub whose referenced contents are identical to a tracked file.
+///
+/// This usually means that either the git stub is redundant, or the tracked
+/// file should have been replaced with a git stub. Returned by
+/// [`Vcs::find_duplicate_contents`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateContents {
+    stub_file: GitStubFile,
+    blob_id: String,
+    tracked_paths: Vec<Utf8PathBuf>,
+}
+
+impl DuplicateContents {
+    /// Returns the git stub file.
+    pub fn stub_file(&self) -> &GitStubFile {
+        &self.stub_file
+    }
+
+    /// Returns the blob ID shared by the referenced file and the tracked
+    /// files.
+    pub fn blob_id(&self) -> &str {
+        &self.blob_id
+    }
+
+    /// Returns the tracked files with identical contents, relative to the
+    /// repository root and sorted by path.
+    pub fn tracked_paths(&self) -> &[Utf8PathBuf] {
+        &self.tracked_paths
+    }
+}
+
+/// A git stub whose commit is not reachable from a protected revision.
+///
+/// This typically happens when a git stub was created on a branch that was
+/// later squash-merged or rebased: once the branch is deleted, the commit is
+/// eventually garbage collected. Returned by
+/// [`Vcs::find_unreachable_commits`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnreachableCommit {
+    stub_file: GitStubFile,
+    exists: bool,
+}
+
+impl UnreachableCommit {
+    /// Returns the git stub file.
+    pub fn stub_file(&self) -> &GitStubFile {
+        &self.stub_file
+    }
+
+    /// Returns false if the commit doesn't exist in the repository at all
+    /// (for example, because it was garbage collected).
+    pub fn commit_exists(&self) -> bool {
+        self.exists
+    }
+}
+
+impl Vcs {
+    /// Finds git stubs in the working tree whose commits are not ancestors
+    /// of `protected_revision`.
+    ///
+    /// `repo_root` must be the repository root. `protected_revision` is
+    /// typically the main branch, and uses the syntax of the VCS (see
+    /// [`Vcs::is_ancestor`]).
+    ///
+    /// Returns an error if `protected_revision` doesn't exist.
+    pub fn find_unreachable_commits(
+        &self,
+        repo_root: &Utf8Path,
+        protected_revision: &str,
+    ) -> Result<Vec<UnreachableCommit>, CheckError> {
+        if !self.revision_exists(repo_root, protected_revision)? {
+            return Err(CheckError::RevisionNotFound {
+                revision: protected_revision.to_owned(),
+            });
+        }
+        let stub_files = self.find_git_stubs(repo_root)?;
+
+        // Many git stubs typically share a commit, so only check each
+        // commit once.
+        let mut reachable = BTreeMap::new();
+        let mut unreachable = Vec::new();
+        for stub_file in stub_files {
+            let commit = stub_file.stub().commit();
+            let (exists, is_reachable) = match reachable.get(&commit) {
+                Some(&result) => result,
+                None => {
+                    let exists = self.commit_exists(repo_root, commit)?;
+                    let is_reachable = exists
+                        && self.is_ancestor(
+                            repo_root,
+                            commit,
+                            protected_revision,
+                        )?;
+                    reachable.insert(commit, (exists, is_reachable));
+                    (exists, is_reachable)
+                }
+            };
+            if !is_reachable {
+                unreachable.push(UnreachableCommit { stub_file, exists });
+            }
+        }
+        Ok(unreachable)
+    }
+
+    /// Finds git stubs whose referenced contents are identical to (have the
+    /// same blob ID as) a tracked file in the working tree.
+    ///
+    /// `repo_root` must be the repository root. Tracked files are read from
+    /// the Git index, so changes that haven't been staged are not taken into
+    /// account. Git stubs that reference files that don't exist are skipped.
+    ///
+    /// Currently only supported for Git. In colocated jj repositories, use
+    /// [`Vcs::git`].
+    pub fn find_duplicate_contents(
+        &self,
+        repo_root: &Utf8Path,
+    ) -> Result<Vec<DuplicateContents>, CheckError> {
+        self.require_git("finding duplicate contents")?;
+        let stub_files = self.find_git_stubs(repo_root)?;
+        let stubs: Vec<_> =
+            stub_files.iter().map(|file| file.stub().clone()).collect();
+        let blob_ids = self.read_blob_ids(repo_root, &stubs)?;
+
+        let tracked = self.tracked_blob_ids(repo_root)?;
+        let duplicates = stub_files
+            .into_iter()
+            .zip(blob_ids)
+            .filter_map(|(stub_file, blob_id)| {
+                let blob_id = blob_id?;
+                let tracked_paths = tracked.get(&blob_id)?.clone();
+                Some(DuplicateContents { stub_file, blob_id, tracked_paths })
+            })
+            .collect();
+        Ok(duplicates)
+    }
+
+    /// Returns a map from blob ID to the regular files in the Git index with
+    /// that ID, excluding git stubs.
+    fn tracked_blob_ids(
+        &self,
+        repo_root: &Utf8Path,
+    ) -> Result<BTreeMap<String, Vec<Utf8PathBuf>>, CheckError> {
+        let args = ["ls-files", "--stage", "-z"];
+        let stdout = self.run(repo_root, &args, None)?;
+
+        let mut tracked = BTreeMap::<_, Vec<_>>::new();
+        for entry in split_output(&stdout, b'\0') {
+            // Each entry is `<mode> <blob ID> <stage>\t<path>`.
+            let parsed =
+                entry.as_str().split_once('\t').and_then(|(info, path)| {
+                    match info.split(' ').collect::<Vec<_>>()[..] {
+                        [mode, blob_id, _] => Some((mode, blob_id, path)),
+                        _ => None,
+                    }
+                });
+            let Some((mode, blob_id, path)) = parsed else {
+                return Err(self
+                    .unexpected_output(
+                        &args,
+                        format!("invalid entry {entry:?}"),
+                    )
+                    .into());
+            };
+            // Skip symlinks (120000) and submodules (160000), whose blob
+            // IDs don't correspond to file contents.
+            let path = Utf8PathBuf::from(path);
+            if !mode.starts_with("100") || path.extension() == Some("gitstub") {
+                continue;
+            }
+            let paths = tracked.entry(blob_id.to_owned()).or_default();
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        for paths in tracked.values_mut() {
+            paths.sort();
+        }
+        Ok(tracked)
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Generation of Rust modules describing materialized files.
+
+use crate::{WriteModuleError, materialize::MaterializedFile};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::{collections::BTreeMap, fmt::Write};
+
+/// A materialized file, as described in a generated module.
+pub(crate) struct ModuleItem {
+    /// The sanitized name, such as `OPENAPI_API_JSON`.
+    pub(crate) name: String,
+    /// The git stub path, relative to the repository root.
+    pub(crate) git_stub_path: Utf8PathBuf,
+    /// The absolute path to the materialized file.
+    pub(crate) output_path: Utf8PathBuf,
+}
+
+/// Returns the items to describe for `files`, with absolute output paths from
+/// `absolute`, sorted by name.
+///
+/// Returns an error if two git stubs have the same sanitized name (or if one
+/// git stub's name is another's with `_PATH` appended), or if a path can't be
+/// represented in a comment or a Cargo directive.
+pub(crate) fn module_items<'a>(
+    files: impl IntoIterator<Item = &'a MaterializedFile>,
+    absolute: impl Fn(&MaterializedFile) -> Utf8PathBuf,
+) -> Result<Vec<ModuleItem>, WriteModuleError> {
+    let mut by_name = BTreeMap::<_, Vec<_>>::new();
+    for file in files {
+        let name = sanitize_name(&file.git_stub_path);
+        by_name.entry(name).or_default().push(file);
+    }
+
+    let mut items = Vec::with_capacity(by_name.len());
+    for (name, files) in &by_name {
+        let path_name = format!("{name}_PATH");
+        if let Some(path_files) = by_name.get(&path_name) {
+            return Err(WriteModuleError::DuplicateName {
+                name: path_name,
+                git_stub_paths: files
+                    .iter()
+                    .chain(path_files)
+                    .map(|file| file.git_stub_path.clone())
+                    .collect(),
+            });
+        }
+        let [file] = files[..] else {
+            return Err(WriteModuleError::DuplicateName {
+                name: name.clone(),
+                git_stub_paths: files
+                    .iter()
+                    .map(|file| file.git_stub_path.clone())
+                    .collect(),
+            });
+        };
+        let git_stub_path = file.git_stub_path.clone();
+        let output_path = absolute(file);
+        for path in [&git_stub_path, &output_path] {
+            if path.as_str().contains(['\n', '\r']) {
+                return Err(WriteModuleError::UnsupportedPath {
+                    path: path.clone(),
+                });
+            }
+        }
+        items.push(ModuleItem {
+            name: name.clone(),
+            git_stub_path,
+            output_path,
+        });
+    }
+    Ok(items)
+}
+
+/// Returns the source of a module with constants for `items`.
+pub(crate) fn module_source(items: &[ModuleItem]) -> String {
+    let mut source = String::from(
+        "// @generated by git-stub-vcs. Do not edit.\n\
+         //\n\
+         // Paths to, and contents of, files materialized from git stubs.\n",
+    );
+    for ModuleItem { name, git_stub_path, output_path } in items {
+        // The Debug representation of a string is a valid Rust string
+        // literal.
+        let path = output_path.as_str();
+        write!(
+            source,
+            "\n/// The path to the file materialized from `{git_stub_path}`.\n\
+             pub const {name}_PATH: &str = {path:?};\n\
+             /// The contents of the file materialized from \
+             `{git_stub_path}`.\n\
+             pub const {name}: &[u8] = include_bytes!({path:?});\n",
+        )
+        .expect("writing to a String can't fail");
+    }
+    source
+}
+
+/// Returns the name of the environment variable set to the output path of
+/// the item named `name`.
+pub(crate) fn env_var_name(name: &str) -> String {
+    format!("GIT_STUB_{name}")
+}
+
+/// Returns the sanitized name for `git_stub_path`: the path without the
+/// `.gitstub` extension, in upper case, with characters other than ASCII
+/// letters and digits replaced by underscores.
+///
+/// For example, `openapi/api-v1.json.gitstub` becomes `OPENAPI_API_V1_JSON`.
+fn sanitize_name(git_stub_path: &Utf8Path) -> String {
+    let path = git_stub_path.with_extension("");
+    let mut name: String = path
+        .as_str()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }
+        })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_name() {
+        let cases = [
+            ("api.json.gitstub", "API_JSON"),
+            ("openapi/api-v1.2.json.gitstub", "OPENAPI_API_V1_2_JSON"),
+            ("1.0.0/spec.gitstub", "_1_0_0_SPEC"),
+            ("_private.bin.gitstub", "__PRIVATE_BIN"),
+            ("caf\u{e9}.txt.gitstub", "CAF__TXT"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                sanitize_name(Utf8Path::new(input)),
+                expected,
+                "input: {input}"
+            );
+        }
+    }
+}
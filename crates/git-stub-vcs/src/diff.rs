@@ -0,0 +1,112 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Comparing the contents referenced by git stubs.
+
+use crate::{DiffError, Vcs};
+use camino::{Utf8Path, Utf8PathBuf};
+use fs_err as fs;
+use git_stub::GitStub;
+use similar::TextDiff;
+use std::fmt;
+
+/// One side of a comparison made by [`Vcs::diff`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffSource {
+    /// The contents referenced by a git stub.
+    GitStub(GitStub),
+
+    /// A file in the working tree, relative to the repository root.
+    File(Utf8PathBuf),
+}
+
+impl fmt::Display for DiffSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiffSource::GitStub(stub) => stub.fmt(f),
+            DiffSource::File(path) => path.fmt(f),
+        }
+    }
+}
+
+impl From<GitStub> for DiffSource {
+    fn from(stub: GitStub) -> Self {
+        DiffSource::GitStub(stub)
+    }
+}
+
+/// The result of comparing two [`DiffSource`]s.
+#[derive(Clone, Debug)]
+pub struct ContentDiff {
+    identical: bool,
+    unified_diff: String,
+}
+
+impl ContentDiff {
+    /// Returns true if both sides have byte-for-byte identical contents.
+    pub fn is_identical(&self) -> bool {
+        self.identical
+    }
+
+    /// Returns a unified diff between the two sides, with three lines of
+    /// context.
+    ///
+    /// The diff is empty if the contents are identical. If either side is not
+    /// valid UTF-8, the diff is a single `Binary files ... differ` line.
+    pub fn unified_diff(&self) -> &str {
+        &self.unified_diff
+    }
+}
+
+impl Vcs {
+    /// Compares the contents of `old` and `new`.
+    ///
+    /// `repo_root` must be the repository root. Git stub contents are read
+    /// with [`read_git_stub_contents`](Self::read_git_stub_contents), and
+    /// the diff is computed in-process.
+    pub fn diff(
+        &self,
+        repo_root: &Utf8Path,
+        old: &DiffSource,
+        new: &DiffSource,
+    ) -> Result<ContentDiff, DiffError> {
+        let old_contents = self.read_diff_source(repo_root, old)?;
+        let new_contents = self.read_diff_source(repo_root, new)?;
+        if old_contents == new_contents {
+            return Ok(ContentDiff {
+                identical: true,
+                unified_diff: String::new(),
+            });
+        }
+
+        let unified_diff = match (
+            std::str::from_utf8(&old_contents),
+            std::str::from_utf8(&new_contents),
+        ) {
+            (Ok(old_text), Ok(new_text)) => {
+                TextDiff::from_lines(old_text, new_text)
+                    .unified_diff()
+                    .header(&old.to_string(), &new.to_string())
+                    .to_string()
+            }
+            _ => format!("Binary files {old} and {new} differ\n"),
+        };
+        Ok(ContentDiff { identical: false, unified_diff })
+    }
+
+    fn read_diff_source(
+        &self,
+        repo_root: &Utf8Path,
+        source: &DiffSource,
+    ) -> Result<Vec<u8>, DiffError> {
+        match source {
+            DiffSource::GitStub(stub) => {
+                Ok(self.read_git_stub_contents(stub, repo_root)?)
+            }
+            DiffSource::File(path) => {
+                let path = repo_root.join(path);
+                fs::read(&path)
+                    .map_err(|error| DiffError::ReadFile { path, error })
+            }
+        }
+    }
+}
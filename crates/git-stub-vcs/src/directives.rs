@@ -0,0 +1,181 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Cargo build script directives.
+
+use camino::Utf8Path;
+use std::{
+    fmt,
+    io::Write,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+/// The environment variables this crate reads, for which
+/// `cargo::rerun-if-env-changed` directives are emitted.
+///
+/// `GIT` and `JJ` are read by [`Vcs::git`](crate::Vcs::git) and
+/// [`Vcs::jj`](crate::Vcs::jj) to locate the VCS binary.
+const ENV_VARS: [&str; 2] = ["GIT", "JJ"];
+
+/// Where a [`Materializer`](crate::Materializer) sends Cargo build script
+/// directives, such as `cargo::rerun-if-changed`.
+///
+/// Materializers created with
+/// [`for_build_script`](crate::Materializer::for_build_script) print
+/// directives to standard output, which is where Cargo reads them from.
+/// Wrapper libraries can use
+/// [`with_directive_sink`](crate::Materializer::with_directive_sink) to
+/// redirect them instead, for example to test or post-process them.
+///
+/// Clones share the same destination, so a clone of a
+/// [`collector`](Self::collector) can be used to read back the directives
+/// that were emitted.
+///
+/// # Examples
+///
+/// ```no_run
+/// use git_stub_vcs::{DirectiveSink, Materializer};
+///
+/// let sink = DirectiveSink::collector();
+/// let materializer = Materializer::standard("../..", "/tmp/output")
+///     .expect("VCS detected at repo root")
+///     .with_directive_sink(sink.clone());
+/// materializer
+///     .materialize("openapi/my-api/my-api-1.0.0-abc123.json.gitstub")
+///     .expect("materialized successfully");
+/// for directive in sink.collected() {
+///     println!("{directive}");
+/// }
+/// ```
+#[derive(Clone)]
+pub struct DirectiveSink {
+    inner: Arc<SinkInner>,
+}
+
+struct SinkInner {
+    kind: SinkKind,
+    /// Whether `cargo::rerun-if-env-changed` directives have been emitted.
+    env_emitted: AtomicBool,
+}
+
+enum SinkKind {
+    Stdout,
+    Writer(Mutex<Box<dyn Write + Send>>),
+    Collector(Mutex<Vec<String>>),
+    Discard,
+}
+
+impl DirectiveSink {
+    /// Returns a sink that prints directives to standard output, for Cargo
+    /// to read.
+    pub fn stdout() -> Self {
+        Self::new(SinkKind::Stdout)
+    }
+
+    /// Returns a sink that writes directives to `writer`, one per line.
+    ///
+    /// Errors writing to `writer` are ignored.
+    pub fn writer(writer: impl Write + Send + 'static) -> Self {
+        Self::new(SinkKind::Writer(Mutex::new(Box::new(writer))))
+    }
+
+    /// Returns a sink that collects directives in memory, to be read back
+    /// with [`collected`](Self::collected).
+    pub fn collector() -> Self {
+        Self::new(SinkKind::Collector(Mutex::new(Vec::new())))
+    }
+
+    /// Returns a sink that discards directives.
+    ///
+    /// This is the default for materializers not created for build scripts.
+    pub fn discard() -> Self {
+        Self::new(SinkKind::Discard)
+    }
+
+    fn new(kind: SinkKind) -> Self {
+        Self {
+            inner: Arc::new(SinkInner {
+                kind,
+                env_emitted: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Returns the directives collected so far, in the order they were
+    /// emitted, without trailing newlines.
+    ///
+    /// Returns an empty list if this isn't a
+    /// [`collector`](Self::collector).
+    pub fn collected(&self) -> Vec<String> {
+        match &self.inner.kind {
+            SinkKind::Collector(directives) => lock(directives).clone(),
+            SinkKind::Stdout | SinkKind::Writer(_) | SinkKind::Discard => {
+                Vec::new()
+            }
+        }
+    }
+
+    /// Emits a single directive, such as `cargo::rerun-if-changed=foo`.
+    fn emit(&self, directive: String) {
+        match &self.inner.kind {
+            SinkKind::Stdout => println!("{directive}"),
+            SinkKind::Writer(writer) => {
+                let _ = writeln!(lock(writer), "{directive}");
+            }
+            SinkKind::Collector(directives) => {
+                lock(directives).push(directive);
+            }
+            SinkKind::Discard => {}
+        }
+    }
+
+    /// Emits `cargo::rerun-if-env-changed` for each environment variable
+    /// this crate reads, the first time this is called for this sink (or
+    /// any of its clones).
+    pub(crate) fn emit_rerun_if_env_changed(&self) {
+        if !self.inner.env_emitted.swap(true, Ordering::SeqCst) {
+            for var in ENV_VARS {
+                self.emit(format!("cargo::rerun-if-env-changed={var}"));
+            }
+        }
+    }
+
+    /// Emits `cargo::warning` with `message`.
+    ///
+    /// Cargo directives are line-based, so newlines in `message` are replaced
+    /// with spaces.
+    pub(crate) fn emit_warning(&self, message: &str) {
+        let message = message.replace(['\n', '\r'], " ");
+        self.emit(format!("cargo::warning={message}"));
+    }
+
+    /// Emits `cargo::rustc-env` setting `var` to `value`.
+    pub(crate) fn emit_rustc_env(&self, var: &str, value: &str) {
+        self.emit(format!("cargo::rustc-env={var}={value}"));
+    }
+
+    /// Emits `cargo::rerun-if-changed` for `path`.
+    pub(crate) fn emit_rerun_if_changed(&self, path: &Utf8Path) {
+        self.emit(format!("cargo::rerun-if-changed={path}"));
+    }
+}
+
+impl fmt::Debug for DirectiveSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match &self.inner.kind {
+            SinkKind::Stdout => "stdout",
+            SinkKind::Writer(_) => "writer",
+            SinkKind::Collector(_) => "collector",
+            SinkKind::Discard => "discard",
+        };
+        f.debug_struct("DirectiveSink").field("kind", &kind).finish()
+    }
+}
+
+/// Locks `mutex`, ignoring poisoning: a panic while emitting a directive
+/// leaves nothing half-updated.
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|error| error.into_inner())
+}
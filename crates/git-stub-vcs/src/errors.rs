@@ -4,8 +4,10 @@
 
 use crate::VcsName;
 use camino::Utf8PathBuf;
-use git_stub::{GitStub, GitStubParseError};
-use std::{ffi::OsString, io};
+use git_stub::{
+    CommitHashParseError, GitCommitHash, GitStub, GitStubParseError,
+};
+use std::{ffi::OsString, io, time::Duration};
 use thiserror::Error;
 
 // ---- VCS errors ----
@@ -68,6 +70,55 @@ pub enum VcsDetectError {
         repo_root: Utf8PathBuf,
     },
 
+    /// Neither `.git` nor `.jj` was found in the starting directory or any
+    /// of its ancestors.
+    #[error(
+        "no VCS found at {start} or any parent directory \
+         (expected .git or .jj)"
+    )]
+    NotFoundInAncestors {
+        /// The directory the search started from.
+        start: Utf8PathBuf,
+    },
+
+    /// A `.git` file (as in Git worktrees and submodules) doesn't start
+    /// with `gitdir:`.
+    #[error("{path} is a file, but does not start with `gitdir:`")]
+    InvalidGitFile {
+        /// The path to the `.git` file.
+        path: Utf8PathBuf,
+    },
+
+    /// A `.git` file points to a Git directory that doesn't exist.
+    ///
+    /// This typically means that the worktree was pruned or the main
+    /// repository was moved.
+    #[error(
+        "{path} points to {git_dir}, which does not exist \
+         (stale worktree or submodule?)"
+    )]
+    DanglingGitDir {
+        /// The path to the `.git` file.
+        path: Utf8PathBuf,
+        /// The Git directory it points to.
+        git_dir: Utf8PathBuf,
+    },
+
+    /// The `.jj/repo` file of a secondary jj workspace points to a
+    /// repository that doesn't exist.
+    ///
+    /// This typically means that the main workspace was moved or deleted.
+    #[error(
+        "{path} points to {repo_dir}, which does not exist \
+         (stale jj workspace?)"
+    )]
+    DanglingJjRepo {
+        /// The path to the `.jj/repo` file.
+        path: Utf8PathBuf,
+        /// The repository directory it points to.
+        repo_dir: Utf8PathBuf,
+    },
+
     /// A VCS environment variable is not valid UTF-8.
     #[error(transparent)]
     Env(#[from] VcsEnvError),
@@ -127,6 +178,26 @@ pub enum ShallowCloneError {
         /// The stdout content that could not be interpreted.
         stdout: String,
     },
+
+    /// The VCS command did not finish within the configured timeout, and
+    /// was killed.
+    #[error(
+        "{vcs_name} timed out after {timeout:?} checking for shallow \
+         clone"
+    )]
+    TimedOut {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+        /// The timeout.
+        timeout: Duration,
+    },
+
+    /// The VCS command was cancelled, and was killed.
+    #[error("{vcs_name} was cancelled while checking for shallow clone")]
+    Cancelled {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+    },
 }
 
 /// An error that occurs while reading the contents of a
@@ -161,6 +232,428 @@ pub enum ReadContentsError {
         /// The stderr output from the VCS.
         stderr: String,
     },
+
+    /// The VCS command did not finish within the configured timeout, and
+    /// was killed.
+    #[error("{vcs_name} timed out after {timeout:?} reading {stub}")]
+    TimedOut {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+        /// The stub that was requested.
+        stub: GitStub,
+        /// The timeout.
+        timeout: Duration,
+    },
+
+    /// The VCS command was cancelled, and was killed.
+    #[error("{vcs_name} was cancelled while reading {stub}")]
+    Cancelled {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+        /// The stub that was requested.
+        stub: GitStub,
+    },
+}
+
+/// An error that occurs while following a chain of git stubs.
+///
+/// Each variant includes the chain of git stubs followed so far, starting
+/// with the git stub that was read.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ResolveChainError {
+    /// Failed to read the contents referenced by the last git stub in the
+    /// chain.
+    #[error("failed to read git stub chain {}", display_chain(.chain))]
+    ReadContents {
+        /// The chain of git stubs.
+        chain: Vec<GitStub>,
+        /// The underlying error.
+        #[source]
+        error: Box<ReadContentsError>,
+    },
+
+    /// The last git stub in the chain references a `.gitstub` file with an
+    /// invalid format.
+    #[error(
+        "invalid git stub format at end of chain {}",
+        display_chain(.chain)
+    )]
+    InvalidGitStub {
+        /// The chain of git stubs.
+        chain: Vec<GitStub>,
+        /// Details about the parsing error.
+        #[source]
+        error: GitStubParseError,
+    },
+
+    /// The chain refers back to a git stub earlier in the chain.
+    #[error("git stub chain has a cycle: {}", display_chain(.chain))]
+    Cycle {
+        /// The chain of git stubs, ending with the repeated git stub.
+        chain: Vec<GitStub>,
+    },
+
+    /// The chain is longer than the maximum depth.
+    #[error(
+        "git stub chain exceeds maximum depth {max_depth}: {}",
+        display_chain(.chain)
+    )]
+    TooDeep {
+        /// The chain of git stubs.
+        chain: Vec<GitStub>,
+        /// The maximum depth.
+        max_depth: usize,
+    },
+}
+
+fn display_chain(chain: &[GitStub]) -> String {
+    let stubs: Vec<_> = chain.iter().map(|stub| stub.to_string()).collect();
+    stubs.join(" -> ")
+}
+
+/// An error that occurs while reading a blob from the Git index.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ReadIndexError {
+    /// The VCS does not have an index (only Git does).
+    #[error("{vcs_name} does not have an index")]
+    Unsupported {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+    },
+
+    /// Failed to spawn the VCS process.
+    #[error("failed to run {vcs_name} at {binary_path:?} in {repo_root}")]
+    SpawnFailed {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+        /// The path to the VCS executable.
+        binary_path: String,
+        /// The working directory where the command was run.
+        repo_root: Utf8PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        source: io::Error,
+    },
+
+    /// The VCS command failed.
+    #[error(
+        "{vcs_name} failed to read {path} from the index \
+         ({exit_status}): {stderr}"
+    )]
+    VcsFailed {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+        /// The path that was requested.
+        path: Utf8PathBuf,
+        /// A human-readable description of the exit status (e.g.,
+        /// "exit code 128" or "killed by signal").
+        exit_status: String,
+        /// The stderr output from the VCS.
+        stderr: String,
+    },
+
+    /// The VCS command succeeded but returned unexpected output.
+    #[error(
+        "{vcs_name} returned unexpected output while reading {path} \
+         from the index: {message}"
+    )]
+    UnexpectedOutput {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+        /// The path that was requested.
+        path: Utf8PathBuf,
+        /// A description of what was unexpected.
+        message: String,
+    },
+
+    /// The VCS command did not finish within the configured timeout, and
+    /// was killed.
+    #[error(
+        "{vcs_name} timed out after {timeout:?} reading {path} from \
+         the index"
+    )]
+    TimedOut {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+        /// The path that was requested.
+        path: Utf8PathBuf,
+        /// The timeout.
+        timeout: Duration,
+    },
+
+    /// The VCS command was cancelled, and was killed.
+    #[error("{vcs_name} was cancelled while reading {path} from the index")]
+    Cancelled {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+        /// The path that was requested.
+        path: Utf8PathBuf,
+    },
+}
+
+/// An error that occurs while running a VCS command.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum VcsCommandError {
+    /// The operation is not supported by this VCS.
+    #[error("{operation} is not supported by {vcs_name}")]
+    Unsupported {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+        /// A description of the operation.
+        operation: &'static str,
+    },
+
+    /// Failed to spawn the VCS process.
+    #[error("failed to run {vcs_name} at {binary_path:?} in {repo_root}")]
+    SpawnFailed {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+        /// The path to the VCS executable.
+        binary_path: String,
+        /// The working directory where the command was run.
+        repo_root: Utf8PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        source: io::Error,
+    },
+
+    /// The VCS command failed.
+    #[error("`{vcs_name} {args}` failed ({exit_status}): {stderr}")]
+    Failed {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+        /// The arguments passed to the VCS, separated by spaces.
+        args: String,
+        /// A human-readable description of the exit status (e.g.,
+        /// "exit code 128" or "killed by signal").
+        exit_status: String,
+        /// The stderr output from the VCS.
+        stderr: String,
+    },
+
+    /// The VCS command succeeded but returned unexpected output.
+    #[error("`{vcs_name} {args}` returned unexpected output: {message}")]
+    UnexpectedOutput {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+        /// The arguments passed to the VCS, separated by spaces.
+        args: String,
+        /// A description of what was unexpected.
+        message: String,
+    },
+
+    /// The VCS command did not finish within the configured timeout, and
+    /// was killed.
+    #[error("`{vcs_name} {args}` timed out after {timeout:?}")]
+    TimedOut {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+        /// The arguments passed to the VCS, separated by spaces.
+        args: String,
+        /// The timeout.
+        timeout: Duration,
+    },
+
+    /// The VCS command was cancelled, and was killed.
+    #[error("`{vcs_name} {args}` was cancelled")]
+    Cancelled {
+        /// The name of the VCS.
+        vcs_name: VcsName,
+        /// The arguments passed to the VCS, separated by spaces.
+        args: String,
+    },
+}
+
+/// An error that occurs while finding git stubs in a repository.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum FindGitStubsError {
+    /// Failed to list files in the repository.
+    #[error("failed to list git stubs")]
+    List(#[from] VcsCommandError),
+
+    /// Failed to read a git stub.
+    #[error("failed to read git stub {path}")]
+    ReadGitStub {
+        /// The path to the git stub, relative to the repository root.
+        path: Utf8PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        error: io::Error,
+    },
+
+    /// A git stub has an invalid format.
+    #[error("invalid git stub format in {path}")]
+    InvalidGitStub {
+        /// The path to the git stub, relative to the repository root.
+        path: Utf8PathBuf,
+        /// Details about the parsing error.
+        #[source]
+        error: GitStubParseError,
+    },
+}
+
+/// An error that occurs while checking git stubs for problems.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum CheckError {
+    /// Failed to find git stubs.
+    #[error("failed to find git stubs")]
+    FindGitStubs(#[from] FindGitStubsError),
+
+    /// A VCS command failed.
+    #[error("failed to query the VCS")]
+    Vcs(#[from] VcsCommandError),
+
+    /// The protected revision passed to
+    /// [`Vcs::find_unreachable_commits`](crate::Vcs::find_unreachable_commits)
+    /// doesn't exist.
+    #[error("protected revision {revision:?} not found")]
+    RevisionNotFound {
+        /// The revision.
+        revision: String,
+    },
+}
+
+/// An error that occurs while updating keep-alive refs.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum KeepRefsError {
+    /// Failed to find git stubs.
+    #[error("failed to find git stubs")]
+    FindGitStubs(#[from] FindGitStubsError),
+
+    /// A VCS command failed.
+    #[error("failed to update keep-alive refs")]
+    Vcs(#[from] VcsCommandError),
+}
+
+/// An error that occurs while re-pinning git stubs.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RepinError {
+    /// Failed to find git stubs with unreachable commits.
+    #[error("failed to find unreachable git stub commits")]
+    Check(#[from] CheckError),
+
+    /// A VCS command failed.
+    #[error("failed to query the VCS")]
+    Vcs(#[from] VcsCommandError),
+}
+
+/// An error that occurs while parsing a [`CommitMap`](crate::CommitMap).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum CommitMapParseError {
+    /// A line doesn't consist of an old and a new commit hash.
+    #[error("line {line_number}: expected `<old> <new>`, got {line:?}")]
+    InvalidLine {
+        /// The 1-based line number.
+        line_number: usize,
+        /// The contents of the line.
+        line: String,
+    },
+
+    /// A commit hash is invalid.
+    #[error("line {line_number}: invalid commit hash")]
+    InvalidHash {
+        /// The 1-based line number.
+        line_number: usize,
+        /// The underlying parse error.
+        #[source]
+        error: CommitHashParseError,
+    },
+}
+
+/// An error that occurs while diffing the contents referenced by git stubs.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum DiffError {
+    /// Failed to read the contents referenced by a git stub.
+    #[error("failed to read git stub contents")]
+    ReadContents(#[from] ReadContentsError),
+
+    /// Failed to read a file in the working tree.
+    #[error("failed to read {path}")]
+    ReadFile {
+        /// The path to the file.
+        path: Utf8PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        error: io::Error,
+    },
+}
+
+// ---- Filter errors ----
+
+/// An error that aborts a Git long-running filter process.
+///
+/// Errors for individual smudge or clean requests are reported back to Git
+/// instead; see [`FilterRequestError`].
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum FilterError {
+    /// An I/O error occurred while communicating with Git.
+    #[error("I/O error while communicating with Git")]
+    Io(#[from] io::Error),
+
+    /// Git sent something that doesn't follow the filter protocol.
+    #[error("filter protocol error: {message}")]
+    Protocol {
+        /// A description of the protocol violation.
+        message: String,
+    },
+}
+
+/// An error that occurs while handling a single smudge or clean request.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum FilterRequestError {
+    /// Failed to read the file referenced by a git stub.
+    #[error("failed to read contents of {pathname} from history")]
+    ReadContents {
+        /// The path of the file being filtered.
+        pathname: Utf8PathBuf,
+        /// The underlying error.
+        #[source]
+        error: Box<ReadContentsError>,
+    },
+
+    /// Failed to read the git stub staged in the index.
+    #[error("failed to read git stub for {pathname} from the index")]
+    ReadIndex {
+        /// The path of the file being filtered.
+        pathname: Utf8PathBuf,
+        /// The underlying error.
+        #[source]
+        error: ReadIndexError,
+    },
+
+    /// The working tree contents no longer match the file referenced by the
+    /// git stub in the index.
+    #[error(
+        "{pathname} differs from {stub} (files backed by git stubs are \
+         read-only: to point at a different version, replace the file \
+         contents with a new git stub)"
+    )]
+    ContentsModified {
+        /// The path of the file being filtered.
+        pathname: Utf8PathBuf,
+        /// The git stub in the index.
+        stub: GitStub,
+    },
+
+    /// Git sent a request for a command other than smudge or clean.
+    #[error("unsupported filter command {command:?} for {pathname}")]
+    UnknownCommand {
+        /// The command Git requested.
+        command: String,
+        /// The path of the file being filtered.
+        pathname: Utf8PathBuf,
+    },
 }
 
 // ---- Materialization errors ----
@@ -192,10 +685,10 @@ pub enum MaterializeError {
         path: Utf8PathBuf,
     },
 
-    /// Failed to read the Git stub.
-    #[error("failed to read Git stub {path}")]
+    /// Failed to read the git stub.
+    #[error("failed to read git stub {path}")]
     ReadGitStub {
-        /// The path to the Git stub.
+        /// The path to the git stub.
         path: Utf8PathBuf,
         /// The underlying I/O error.
         #[source]
@@ -203,15 +696,44 @@ pub enum MaterializeError {
     },
 
     /// The git stub has an invalid format.
-    #[error("invalid Git stub format in {path}")]
+    #[error("invalid git stub format in {path}")]
     InvalidGitStub {
-        /// The path to the Git stub.
+        /// The path to the git stub.
         path: Utf8PathBuf,
         /// Details about the parsing error.
         #[source]
         error: GitStubParseError,
     },
 
+    /// Failed to read a git stub file from a commit.
+    #[error("failed to read git stub {path} at commit {commit}")]
+    ReadGitStubAtCommit {
+        /// The path to the git stub, relative to the repository root.
+        path: Utf8PathBuf,
+        /// The commit the git stub was read from.
+        commit: GitCommitHash,
+        /// The underlying error.
+        #[source]
+        error: Box<ReadContentsError>,
+    },
+
+    /// Failed to resolve the revision to read git stub files from.
+    #[error("failed to resolve revision {revision:?}")]
+    ResolveRevision {
+        /// The revision.
+        revision: String,
+        /// The underlying error.
+        #[source]
+        error: VcsCommandError,
+    },
+
+    /// The revision to read git stub files from doesn't exist.
+    #[error("revision {revision:?} not found")]
+    RevisionNotFound {
+        /// The revision.
+        revision: String,
+    },
+
     /// VCS detection failed.
     #[error("VCS detection failed")]
     VcsDetect(#[from] VcsDetectError),
@@ -220,6 +742,10 @@ pub enum MaterializeError {
     #[error("failed to read git stub contents")]
     ReadContents(#[from] ReadContentsError),
 
+    /// Failed to follow a chain of git stubs.
+    #[error("failed to resolve git stub chain")]
+    ResolveChain(#[from] ResolveChainError),
+
     /// Failed to check whether the repository is a shallow clone.
     #[error("failed to check for shallow clone at {repo_root}")]
     ShallowCloneCheck {
@@ -262,6 +788,14 @@ pub enum MaterializeError {
         #[source]
         error: AtomicWriteError,
     },
+
+    /// Asynchronous materialization was cancelled because the Tokio runtime
+    /// is shutting down.
+    #[error("materializing {path} was cancelled")]
+    Cancelled {
+        /// The git stub path.
+        path: Utf8PathBuf,
+    },
 }
 
 fn shallow_clone_msg(vcs: &VcsName) -> &'static str {
@@ -274,6 +808,210 @@ fn shallow_clone_msg(vcs: &VcsName) -> &'static str {
     }
 }
 
+/// Errors that can occur while materializing several git stubs with
+/// [`Materializer::materialize_all`](crate::Materializer::materialize_all).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum MaterializeAllError {
+    /// Two or more git stubs would be materialized to the same output path.
+    ///
+    /// This is checked before any git stubs are materialized.
+    #[error(
+        "git stubs {} would all be materialized to {output_path}",
+        display_paths(.git_stub_paths)
+    )]
+    DuplicateOutput {
+        /// The output path.
+        output_path: Utf8PathBuf,
+        /// The git stub paths, in the order they were passed in.
+        git_stub_paths: Vec<Utf8PathBuf>,
+    },
+
+    /// Materializing one or more git stubs failed.
+    ///
+    /// All other git stubs were materialized.
+    #[error(
+        "failed to materialize {} git stub(s): {}",
+        .errors.len(),
+        display_paths(.errors.iter().map(|(path, _)| path))
+    )]
+    Failed {
+        /// The git stub paths that failed, along with their errors, in the
+        /// order they were passed in.
+        errors: Vec<(Utf8PathBuf, MaterializeError)>,
+    },
+}
+
+/// Errors that can occur while removing stale outputs with
+/// [`Materializer::prune`](crate::Materializer::prune).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum PruneError {
+    /// Failed to read a directory within the output directory.
+    #[error("failed to read directory {path}")]
+    ReadDir {
+        /// The directory path.
+        path: Utf8PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        error: io::Error,
+    },
+
+    /// Failed to remove a stale file.
+    #[error("failed to remove stale file {path}")]
+    RemoveFile {
+        /// The file path.
+        path: Utf8PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        error: io::Error,
+    },
+
+    /// Failed to remove a directory left empty.
+    #[error("failed to remove empty directory {path}")]
+    RemoveDir {
+        /// The directory path.
+        path: Utf8PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        error: io::Error,
+    },
+}
+
+/// Errors that can occur while building a manifest with
+/// [`Materializer::manifest`](crate::Materializer::manifest).
+#[cfg(feature = "manifest")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "manifest")))]
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ManifestError {
+    /// Failed to read a materialized file.
+    #[error("failed to read materialized file {path}")]
+    ReadOutput {
+        /// The path to the materialized file.
+        path: Utf8PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        error: io::Error,
+    },
+}
+
+/// Errors that can occur while writing a manifest with
+/// [`Manifest::write_json`](crate::Manifest::write_json).
+#[cfg(feature = "manifest")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "manifest")))]
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum WriteManifestError {
+    /// Failed to create the manifest's parent directory.
+    #[error("failed to create directory {path}")]
+    CreateDir {
+        /// The directory path.
+        path: Utf8PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        error: io::Error,
+    },
+
+    /// Failed to write the manifest.
+    #[error("failed to write manifest to {path}")]
+    Write {
+        /// The manifest path.
+        path: Utf8PathBuf,
+        /// The underlying error.
+        #[source]
+        error: AtomicWriteError,
+    },
+}
+
+/// Errors that can occur while writing a depfile with
+/// [`Materializer::write_depfile`](crate::Materializer::write_depfile).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum WriteDepfileError {
+    /// A path contains a newline, contains a colon (other than in a Windows
+    /// prefix such as `C:`), or ends with a backslash, none of which can be
+    /// represented in a depfile.
+    #[error("path {path:?} can't be represented in a depfile")]
+    UnsupportedPath {
+        /// The path.
+        path: Utf8PathBuf,
+    },
+
+    /// Failed to create the depfile's parent directory.
+    #[error("failed to create directory {path}")]
+    CreateDir {
+        /// The directory path.
+        path: Utf8PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        error: io::Error,
+    },
+
+    /// Failed to write the depfile.
+    #[error("failed to write depfile to {path}")]
+    Write {
+        /// The depfile path.
+        path: Utf8PathBuf,
+        /// The underlying error.
+        #[source]
+        error: AtomicWriteError,
+    },
+}
+
+/// Errors that can occur while writing a Rust module with
+/// [`Materializer::write_rust_module`](crate::Materializer::write_rust_module).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum WriteModuleError {
+    /// Two or more git stubs map to the same constant name.
+    #[error(
+        "git stubs {} would all define the constant {name}",
+        display_paths(.git_stub_paths)
+    )]
+    DuplicateName {
+        /// The constant name.
+        name: String,
+        /// The git stub paths, sorted.
+        git_stub_paths: Vec<Utf8PathBuf>,
+    },
+
+    /// A path contains a newline, which can't be represented in a Cargo
+    /// directive.
+    #[error("path {path:?} contains a newline, which is unsupported")]
+    UnsupportedPath {
+        /// The path.
+        path: Utf8PathBuf,
+    },
+
+    /// Failed to create the module's parent directory.
+    #[error("failed to create directory {path}")]
+    CreateDir {
+        /// The directory path.
+        path: Utf8PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        error: io::Error,
+    },
+
+    /// Failed to write the module.
+    #[error("failed to write Rust module to {path}")]
+    Write {
+        /// The module path.
+        path: Utf8PathBuf,
+        /// The underlying error.
+        #[source]
+        error: AtomicWriteError,
+    },
+}
+
+fn display_paths<'a>(
+    paths: impl IntoIterator<Item = &'a Utf8PathBuf>,
+) -> String {
+    let paths: Vec<_> = paths.into_iter().map(|path| path.as_str()).collect();
+    paths.join(", ")
+}
+
 /// An error that occurred during an atomic file write.
 #[derive(Debug, Error)]
 #[non_exhaustive]
@@ -0,0 +1,408 @@
+// Copyright 2026 Oxide Computer Company
+
+//! A Git long-running filter process for files backed by git stubs.
+//!
+//! With this filter, the repository stores git stub text (`commit:path`) for
+//! files marked with the `gitstub` filter attribute, while the working tree
+//! contains the referenced contents. This is similar to how Git LFS uses
+//! pointer files.
+//!
+//! To enable the filter:
+//!
+//! ```text
+//! git config filter.gitstub.process "git-stub filter-process"
+//! git config filter.gitstub.required true
+//! echo 'openapi/archived/*.json filter=gitstub' >> .gitattributes
+//! ```
+//!
+//! For the protocol, see
+//! [gitattributes(5)](https://git-scm.com/docs/gitattributes#_long_running_filter_process)
+//! and
+//! [gitprotocol-common(5)](https://git-scm.com/docs/gitprotocol-common#_pkt_line_format).
+
+use crate::{FilterError, FilterRequestError, Vcs, VcsEnvError};
+use camino::{Utf8Path, Utf8PathBuf};
+use git_stub::GitStub;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+/// The maximum amount of data in a single pkt-line.
+const MAX_PACKET_DATA: usize = 65516;
+
+/// A Git long-running filter process that expands git stubs on checkout
+/// (smudge) and collapses them back on add (clean).
+///
+/// Files that do not hold git stub text in the repository are passed through
+/// unchanged, so the `gitstub` attribute can be applied to a directory that
+/// contains a mix of stubbed and regular files.
+#[derive(Debug, Clone)]
+pub struct FilterProcess {
+    repo_root: Utf8PathBuf,
+    vcs: Vcs,
+}
+
+impl FilterProcess {
+    /// Creates a new filter process for the repository at `repo_root`.
+    ///
+    /// Git starts filter processes at the root of the working tree, so
+    /// `repo_root` is typically `"."`.
+    ///
+    /// Filters are a Git feature, so this always uses Git (see
+    /// [`Vcs::git`]), even in colocated jj repositories. Since the filter
+    /// runs as a child of Git, it inherits Git's environment (for example,
+    /// `GIT_DIR` with `git --git-dir`), so [environment
+    /// isolation](Vcs#environment-isolation) is disabled.
+    pub fn new(repo_root: impl Into<Utf8PathBuf>) -> Result<Self, VcsEnvError> {
+        let vcs = Vcs::git()?.with_env_isolation(false);
+        Ok(FilterProcess { repo_root: repo_root.into(), vcs })
+    }
+
+    /// Handles a smudge request: converts repository contents into working
+    /// tree contents.
+    ///
+    /// If `contents` is a git stub, returns the contents of the file it
+    /// references. Otherwise, returns `contents` unchanged.
+    pub fn smudge(
+        &self,
+        pathname: &Utf8Path,
+        contents: &[u8],
+    ) -> Result<Vec<u8>, FilterRequestError> {
+        let Some(stub) = parse_stub(contents) else {
+            return Ok(contents.to_vec());
+        };
+        self.vcs.read_git_stub_contents(&stub, &self.repo_root).map_err(
+            |error| FilterRequestError::ReadContents {
+                pathname: pathname.to_owned(),
+                error: Box::new(error),
+            },
+        )
+    }
+
+    /// Handles a clean request: converts working tree contents into
+    /// repository contents.
+    ///
+    /// * If `contents` is already a git stub, it is returned unchanged. This
+    ///   is how a file is pointed at a different version.
+    /// * If the index has a git stub for `pathname` and `contents` matches the
+    ///   file it references, the git stub from the index is returned.
+    /// * If the index has a git stub for `pathname` but `contents` differs,
+    ///   an error is returned.
+    /// * Otherwise, `contents` is returned unchanged.
+    pub fn clean(
+        &self,
+        pathname: &Utf8Path,
+        contents: &[u8],
+    ) -> Result<Vec<u8>, FilterRequestError> {
+        if parse_stub(contents).is_some() {
+            return Ok(contents.to_vec());
+        }
+
+        let index_blob = self
+            .vcs
+            .read_index_blob(&self.repo_root, pathname)
+            .map_err(|error| FilterRequestError::ReadIndex {
+                pathname: pathname.to_owned(),
+                error,
+            })?;
+        let Some((index_blob, stub)) = index_blob
+            .and_then(|blob| parse_stub(&blob).map(|stub| (blob, stub)))
+        else {
+            return Ok(contents.to_vec());
+        };
+
+        let expected = self.smudge(pathname, &index_blob)?;
+        if expected == contents {
+            // Return the blob as-is rather than the canonical form of the
+            // stub, so that Git doesn't see a non-canonical stub as modified.
+            Ok(index_blob)
+        } else {
+            Err(FilterRequestError::ContentsModified {
+                pathname: pathname.to_owned(),
+                stub,
+            })
+        }
+    }
+
+    /// Runs the filter protocol, reading requests from `input` and writing
+    /// responses to `output` until Git closes `input`.
+    ///
+    /// Typically, `input` is standard input and `output` is standard output.
+    /// Errors for individual requests, including requests for commands other
+    /// than smudge and clean, are reported to Git as failures and passed to
+    /// `on_error`; the session then continues. Git shows a filter process's
+    /// standard error to the user, so `on_error` typically prints the error
+    /// there.
+    ///
+    /// Returns an error if communication with Git fails.
+    pub fn run(
+        &self,
+        input: impl Read,
+        output: impl Write,
+        mut on_error: impl FnMut(FilterRequestError),
+    ) -> Result<(), FilterError> {
+        let mut input = BufReader::new(input);
+        let mut output = BufWriter::new(output);
+        handshake(&mut input, &mut output)?;
+
+        loop {
+            let Some(headers) = read_text_list(&mut input)? else {
+                // Git closed the pipe: we're done.
+                return Ok(());
+            };
+            let command = find_header(&headers, "command")?;
+            let pathname =
+                Utf8PathBuf::from(find_header(&headers, "pathname")?);
+            let contents = read_contents(&mut input)?;
+
+            let result = match command {
+                "smudge" => self.smudge(&pathname, &contents),
+                "clean" => self.clean(&pathname, &contents),
+                other => Err(FilterRequestError::UnknownCommand {
+                    command: other.to_owned(),
+                    pathname: pathname.clone(),
+                }),
+            };
+            match result {
+                Ok(filtered) => {
+                    write_packet(&mut output, b"status=success\n")?;
+                    write_flush(&mut output)?;
+                    for chunk in filtered.chunks(MAX_PACKET_DATA) {
+                        write_packet(&mut output, chunk)?;
+                    }
+                    write_flush(&mut output)?;
+                    // An empty list keeps the status as "success".
+                    write_flush(&mut output)?;
+                }
+                Err(error) => {
+                    on_error(error);
+                    write_packet(&mut output, b"status=error\n")?;
+                    write_flush(&mut output)?;
+                }
+            }
+            output.flush()?;
+        }
+    }
+}
+
+/// Parses `contents` as a git stub, returning `None` if it isn't one.
+pub(crate) fn parse_stub(contents: &[u8]) -> Option<GitStub> {
+    std::str::from_utf8(contents).ok()?.parse().ok()
+}
+
+/// Performs the version and capability negotiation.
+fn handshake(
+    input: &mut impl Read,
+    output: &mut impl Write,
+) -> Result<(), FilterError> {
+    let welcome = read_text_list(input)?.unwrap_or_default();
+    if welcome.first().map(String::as_str) != Some("git-filter-client") {
+        return Err(FilterError::Protocol {
+            message: format!("expected git-filter-client, got {welcome:?}"),
+        });
+    }
+    if !welcome.iter().any(|line| line == "version=2") {
+        return Err(FilterError::Protocol {
+            message: format!("version 2 not offered: {welcome:?}"),
+        });
+    }
+    write_packet(output, b"git-filter-server\n")?;
+    write_packet(output, b"version=2\n")?;
+    write_flush(output)?;
+    output.flush()?;
+
+    // Only advertise the capabilities that Git offered.
+    let offered = read_text_list(input)?.unwrap_or_default();
+    for capability in ["capability=clean", "capability=smudge"] {
+        if offered.iter().any(|line| line == capability) {
+            write_packet(output, format!("{capability}\n").as_bytes())?;
+        }
+    }
+    write_flush(output)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Returns the value of `key` in a list of `key=value` lines.
+fn find_header<'a>(
+    headers: &'a [String],
+    key: &str,
+) -> Result<&'a str, FilterError> {
+    headers
+        .iter()
+        .find_map(|line| {
+            line.strip_prefix(key).and_then(|rest| rest.strip_prefix('='))
+        })
+        .ok_or_else(|| FilterError::Protocol {
+            message: format!("missing {key} in {headers:?}"),
+        })
+}
+
+/// A single pkt-line.
+enum Packet {
+    Data(Vec<u8>),
+    Flush,
+}
+
+/// Reads a pkt-line, returning `None` at end of input.
+fn read_packet(input: &mut impl Read) -> Result<Option<Packet>, FilterError> {
+    let mut len = [0; 4];
+    // Distinguish a clean end of input from a truncated length.
+    match input.read(&mut len[..1])? {
+        0 => return Ok(None),
+        _ => input.read_exact(&mut len[1..])?,
+    }
+    let len = std::str::from_utf8(&len)
+        .ok()
+        .and_then(|len| usize::from_str_radix(len, 16).ok())
+        .ok_or_else(|| FilterError::Protocol {
+            message: format!("invalid pkt-line length {len:?}"),
+        })?;
+    match len {
+        0 => Ok(Some(Packet::Flush)),
+        1..=3 => Err(FilterError::Protocol {
+            message: format!("unexpected special pkt-line {len:04x}"),
+        }),
+        _ => {
+            let mut data = vec![0; len - 4];
+            input.read_exact(&mut data)?;
+            Ok(Some(Packet::Data(data)))
+        }
+    }
+}
+
+/// Reads a flush-terminated list of text lines, returning `None` at end of
+/// input.
+fn read_text_list(
+    input: &mut impl Read,
+) -> Result<Option<Vec<String>>, FilterError> {
+    let mut lines = Vec::new();
+    loop {
+        match read_packet(input)? {
+            None if lines.is_empty() => return Ok(None),
+            None => {
+                return Err(
+                    io::Error::from(io::ErrorKind::UnexpectedEof).into()
+                );
+            }
+            Some(Packet::Flush) => return Ok(Some(lines)),
+            Some(Packet::Data(data)) => {
+                let line = String::from_utf8(data).map_err(|error| {
+                    FilterError::Protocol {
+                        message: format!("non-UTF-8 text packet: {error}"),
+                    }
+                })?;
+                let line = line.strip_suffix('\n').unwrap_or(&line);
+                lines.push(line.to_owned());
+            }
+        }
+    }
+}
+
+/// Reads flush-terminated binary contents.
+fn read_contents(input: &mut impl Read) -> Result<Vec<u8>, FilterError> {
+    let mut contents = Vec::new();
+    loop {
+        match read_packet(input)? {
+            None => {
+                return Err(
+                    io::Error::from(io::ErrorKind::UnexpectedEof).into()
+                );
+            }
+            Some(Packet::Flush) => return Ok(contents),
+            Some(Packet::Data(data)) => contents.extend_from_slice(&data),
+        }
+    }
+}
+
+fn write_packet(output: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    debug_assert!(data.len() <= MAX_PACKET_DATA);
+    write!(output, "{:04x}", data.len() + 4)?;
+    output.write_all(data)
+}
+
+fn write_flush(output: &mut impl Write) -> io::Result<()> {
+    output.write_all(b"0000")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packets(lines: &[&str]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for line in lines {
+            if line.is_empty() {
+                write_flush(&mut buf).unwrap();
+            } else {
+                write_packet(&mut buf, line.as_bytes()).unwrap();
+            }
+        }
+        buf
+    }
+
+    #[test]
+    fn test_handshake() {
+        let input = packets(&[
+            "git-filter-client\n",
+            "version=2\n",
+            "",
+            "capability=clean\n",
+            "capability=smudge\n",
+            "capability=delay\n",
+            "",
+        ]);
+        let mut output = Vec::new();
+        handshake(&mut input.as_slice(), &mut output).unwrap();
+        assert_eq!(
+            output,
+            packets(&[
+                "git-filter-server\n",
+                "version=2\n",
+                "",
+                "capability=clean\n",
+                "capability=smudge\n",
+                "",
+            ]),
+            "should advertise clean and smudge but not delay"
+        );
+    }
+
+    #[test]
+    fn test_handshake_rejects_unknown_version() {
+        let input = packets(&["git-filter-client\n", "version=3\n", ""]);
+        let result = handshake(&mut input.as_slice(), &mut Vec::new());
+        assert!(
+            matches!(result, Err(FilterError::Protocol { .. })),
+            "should reject a client that doesn't offer version 2"
+        );
+    }
+
+    #[test]
+    fn test_read_text_list() {
+        let input = packets(&["command=smudge\n", "pathname=a b.json", ""]);
+        let lines = read_text_list(&mut input.as_slice()).unwrap().unwrap();
+        assert_eq!(lines, ["command=smudge", "pathname=a b.json"]);
+        assert_eq!(find_header(&lines, "pathname").unwrap(), "a b.json");
+        assert!(find_header(&lines, "path").is_err(), "exact key match");
+
+        let lines = read_text_list(&mut [].as_slice()).unwrap();
+        assert!(lines.is_none(), "end of input should return None");
+
+        let input = packets(&["command=smudge\n"]);
+        let result = read_text_list(&mut input.as_slice());
+        assert!(
+            matches!(result, Err(FilterError::Io(_))),
+            "truncated list should be an error"
+        );
+    }
+
+    #[test]
+    fn test_read_packet_invalid_length() {
+        for input in [b"zzzz".as_slice(), b"0002", b"00"] {
+            assert!(
+                read_packet(&mut &input[..]).is_err(),
+                "{:?} should be rejected",
+                String::from_utf8_lossy(input)
+            );
+        }
+    }
+}
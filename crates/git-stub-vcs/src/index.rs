@@ -0,0 +1,95 @@
+// Copyright 2026 Oxide Computer Company
+
+//! A reverse index from commits and paths to the git stubs that reference
+//! them.
+
+use crate::GitStubFile;
+use camino::{Utf8Path, Utf8PathBuf};
+use git_stub::GitCommitHash;
+use std::collections::BTreeMap;
+
+/// An index of git stub files by the commit and path they reference.
+///
+/// Use this to find out which git stubs would break before rewriting history
+/// or deleting a branch. Build an index from the git stubs in the working
+/// tree with [`Vcs::find_git_stubs`](crate::Vcs::find_git_stubs), or at any
+/// commit with [`Vcs::find_git_stubs_at`](crate::Vcs::find_git_stubs_at):
+///
+/// ```no_run
+/// use git_stub_vcs::{GitStubIndex, Vcs};
+///
+/// let vcs = Vcs::git().unwrap();
+/// let stub_files = vcs.find_git_stubs_at(".".into(), "main").unwrap();
+/// let index = GitStubIndex::new(stub_files);
+/// for stub_file in index.by_path("openapi/api.json".into()) {
+///     println!("{}", stub_file.path());
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct GitStubIndex {
+    stub_files: Vec<GitStubFile>,
+    by_commit: BTreeMap<GitCommitHash, Vec<usize>>,
+    by_path: BTreeMap<Utf8PathBuf, Vec<usize>>,
+}
+
+impl GitStubIndex {
+    /// Creates an index over `stub_files`.
+    pub fn new(stub_files: Vec<GitStubFile>) -> Self {
+        let mut by_commit = BTreeMap::<_, Vec<_>>::new();
+        let mut by_path = BTreeMap::<_, Vec<_>>::new();
+        for (i, stub_file) in stub_files.iter().enumerate() {
+            let stub = stub_file.stub();
+            by_commit.entry(stub.commit()).or_default().push(i);
+            by_path.entry(stub.path().to_owned()).or_default().push(i);
+        }
+        GitStubIndex { stub_files, by_commit, by_path }
+    }
+
+    /// Returns all git stub files in the index.
+    pub fn stub_files(&self) -> &[GitStubFile] {
+        &self.stub_files
+    }
+
+    /// Returns the distinct commits referenced by git stubs, in sorted
+    /// order.
+    pub fn commits(&self) -> impl Iterator<Item = GitCommitHash> {
+        self.by_commit.keys().copied()
+    }
+
+    /// Returns the distinct paths referenced by git stubs, in sorted order.
+    pub fn paths(&self) -> impl Iterator<Item = &Utf8Path> {
+        self.by_path.keys().map(|path| path.as_path())
+    }
+
+    /// Returns the git stub files that reference `commit`.
+    pub fn by_commit(
+        &self,
+        commit: GitCommitHash,
+    ) -> impl Iterator<Item = &GitStubFile> {
+        self.lookup(self.by_commit.get(&commit))
+    }
+
+    /// Returns the git stub files that reference `path` (at any commit).
+    ///
+    /// `path` is relative to the repository root, and must match the path in
+    /// the git stub exactly.
+    pub fn by_path(
+        &self,
+        path: &Utf8Path,
+    ) -> impl Iterator<Item = &GitStubFile> {
+        self.lookup(self.by_path.get(path))
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        indexes: Option<&'a Vec<usize>>,
+    ) -> impl Iterator<Item = &'a GitStubFile> {
+        indexes.into_iter().flatten().map(|&i| &self.stub_files[i])
+    }
+}
+
+impl FromIterator<GitStubFile> for GitStubIndex {
+    fn from_iter<I: IntoIterator<Item = GitStubFile>>(iter: I) -> Self {
+        GitStubIndex::new(iter.into_iter().collect())
+    }
+}
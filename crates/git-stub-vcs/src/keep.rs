@@ -0,0 +1,135 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Keep-alive refs that protect commits referenced by git stubs from garbage
+//! collection.
+
+use crate::{GitStubFile, KeepRefsError, Vcs, scan::split_output};
+use camino::Utf8Path;
+use git_stub::GitCommitHash;
+use std::collections::BTreeMap;
+
+/// The prefix of keep-alive refs created by [`Vcs::update_keep_refs`].
+///
+/// Each keep-alive ref is named `refs/git-stub/keep/<commit>` and points at
+/// `<commit>`.
+pub const KEEP_REF_PREFIX: &str = "refs/git-stub/keep/";
+
+/// The changes made by [`Vcs::update_keep_refs`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeepRefsUpdate {
+    created: Vec<String>,
+    deleted: Vec<String>,
+    missing: Vec<GitStubFile>,
+}
+
+impl KeepRefsUpdate {
+    /// Returns the keep-alive refs that were created (or repointed), sorted
+    /// by name.
+    pub fn created(&self) -> &[String] {
+        &self.created
+    }
+
+    /// Returns the keep-alive refs that were deleted because no git stub
+    /// references their commit any more, sorted by name.
+    pub fn deleted(&self) -> &[String] {
+        &self.deleted
+    }
+
+    /// Returns the git stubs whose commits don't exist, so couldn't be
+    /// protected.
+    pub fn missing(&self) -> &[GitStubFile] {
+        &self.missing
+    }
+}
+
+impl Vcs {
+    /// Creates a keep-alive ref for every commit referenced by a git stub in
+    /// the working tree, and deletes keep-alive refs for commits that are no
+    /// longer referenced.
+    ///
+    /// Commits that are only referenced by git stubs are not protected from
+    /// `git gc` once their branch is deleted. A ref under [`KEEP_REF_PREFIX`]
+    /// keeps each such commit alive. Note that refs aren't pushed or fetched
+    /// by default: to share them, configure a refspec such as
+    /// `refs/git-stub/keep/*:refs/git-stub/keep/*`.
+    ///
+    /// `repo_root` must be the repository root. All refs are updated in a
+    /// single transaction using `git update-ref --stdin`.
+    ///
+    /// Currently only supported for Git. In colocated jj repositories, use
+    /// [`Vcs::git`].
+    pub fn update_keep_refs(
+        &self,
+        repo_root: &Utf8Path,
+    ) -> Result<KeepRefsUpdate, KeepRefsError> {
+        self.require_git("updating keep-alive refs")?;
+        let stub_files = self.find_git_stubs(repo_root)?;
+
+        let mut referenced = BTreeMap::new();
+        let mut missing = Vec::new();
+        for stub_file in stub_files {
+            let commit = stub_file.stub().commit();
+            let name = keep_ref_name(commit);
+            if referenced.contains_key(&name)
+                || self.commit_exists(repo_root, commit)?
+            {
+                referenced.insert(name, commit.to_string());
+            } else {
+                missing.push(stub_file);
+            }
+        }
+
+        let existing = self.read_keep_refs(repo_root)?;
+        let mut created = Vec::new();
+        let mut deleted = Vec::new();
+        let mut transaction = String::new();
+        for (name, commit) in &referenced {
+            if existing.get(name) != Some(commit) {
+                transaction.push_str(&format!("update {name} {commit}\n"));
+                created.push(name.clone());
+            }
+        }
+        for name in existing.keys() {
+            if !referenced.contains_key(name) {
+                transaction.push_str(&format!("delete {name}\n"));
+                deleted.push(name.clone());
+            }
+        }
+
+        if !transaction.is_empty() {
+            self.run(
+                repo_root,
+                &["update-ref", "--stdin"],
+                Some(transaction.as_bytes()),
+            )?;
+        }
+        Ok(KeepRefsUpdate { created, deleted, missing })
+    }
+
+    /// Returns a map from keep-alive ref name to the commit it points at.
+    fn read_keep_refs(
+        &self,
+        repo_root: &Utf8Path,
+    ) -> Result<BTreeMap<String, String>, KeepRefsError> {
+        let args = [
+            "for-each-ref",
+            "--format=%(refname) %(objectname)",
+            KEEP_REF_PREFIX,
+        ];
+        let stdout = self.run(repo_root, &args, None)?;
+        let mut refs = BTreeMap::new();
+        for line in split_output(&stdout, b'\n') {
+            let Some((name, object)) = line.split_once(' ') else {
+                return Err(self
+                    .unexpected_output(&args, format!("invalid line {line:?}"))
+                    .into());
+            };
+            refs.insert(name.to_owned(), object.to_owned());
+        }
+        Ok(refs)
+    }
+}
+
+fn keep_ref_name(commit: GitCommitHash) -> String {
+    format!("{KEEP_REF_PREFIX}{commit}")
+}
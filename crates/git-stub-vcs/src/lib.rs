@@ -47,16 +47,70 @@
 //!     .materialize("openapi/my-api/my-api-1.0.0-abc123.json.gitstub")
 //!     .expect("materialized successfully");
 //! ```
+//!
+//! # Git filter
+//!
+//! [`FilterProcess`] implements Git's long-running filter protocol, so that
+//! files stored as git stubs in the repository are expanded in the working
+//! tree. The `git-stub` binary (in the `git-stub-cli` crate) exposes this as
+//! `git-stub filter-process`.
+//!
+//! Similarly, [`textconv`] lets `git diff` show changes to the contents
+//! referenced by git stubs, and is exposed as `git-stub textconv`.
+//!
+//! # Optional features
+//!
+//! - `tokio`: Enables asynchronous versions of some operations, such as
+//!   `Materializer::materialize_async` and
+//!   `Vcs::read_git_stub_contents_async`, using Tokio for process spawning
+//!   and file I/O. *Not enabled by default.*
+//! - `manifest`: Enables `Materializer::manifest`, which records the git
+//!   stubs materialized by a materializer for reproducibility audits and
+//!   SBOMs, and writes it as JSON. *Not enabled by default.*
 
 #![deny(missing_docs)]
+#![cfg_attr(doc_cfg, feature(doc_cfg))]
 
+mod cancel;
+mod chain;
+mod check;
+mod codegen;
+mod diff;
+mod directives;
 mod errors;
+mod filter;
+mod index;
+mod keep;
+#[cfg(feature = "manifest")]
+mod manifest;
 mod materialize;
+mod repin;
+mod scan;
+mod state;
+mod textconv;
 mod vcs;
 
+pub use cancel::CancellationToken;
+pub use chain::{DEFAULT_MAX_CHAIN_DEPTH, GitStubChain};
+pub use check::{DuplicateContents, UnreachableCommit};
+pub use diff::{ContentDiff, DiffSource};
+pub use directives::DirectiveSink;
 pub use errors::{
-    AtomicWriteError, MaterializeError, ReadContentsError, ShallowCloneError,
-    VcsDetectError, VcsEnvError,
+    AtomicWriteError, CheckError, CommitMapParseError, DiffError, FilterError,
+    FilterRequestError, FindGitStubsError, KeepRefsError, MaterializeAllError,
+    MaterializeError, PruneError, ReadContentsError, ReadIndexError,
+    RepinError, ResolveChainError, ShallowCloneError, VcsCommandError,
+    VcsDetectError, VcsEnvError, WriteDepfileError, WriteModuleError,
 };
-pub use materialize::Materializer;
+#[cfg(feature = "manifest")]
+pub use errors::{ManifestError, WriteManifestError};
+pub use filter::FilterProcess;
+pub use index::GitStubIndex;
+pub use keep::{KEEP_REF_PREFIX, KeepRefsUpdate};
+#[cfg(feature = "manifest")]
+pub use manifest::{MANIFEST_VERSION, Manifest, ManifestEntry};
+pub use materialize::{Materialized, Materializer};
+pub use repin::{CommitMap, Repin};
+pub use scan::GitStubFile;
+pub use textconv::textconv;
 pub use vcs::{Vcs, VcsName};
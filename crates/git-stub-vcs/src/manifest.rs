@@ -0,0 +1,222 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Manifests of materialized git stubs.
+
+use crate::{
+    WriteManifestError,
+    materialize::{MaterializedFile, atomic_write},
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use git_stub::{GitCommitHash, GitStub};
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// The version of the manifest's JSON format.
+///
+/// This is incremented whenever a field is removed or changes meaning.
+/// Fields may be added without changing the version.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// A manifest of the git stubs materialized by a
+/// [`Materializer`](crate::Materializer).
+///
+/// Returned by [`Materializer::manifest`](crate::Materializer::manifest).
+/// The manifest is intended for reproducibility audits and for generating
+/// software bills of materials (SBOMs): it records exactly which file, at
+/// which commit, each output was materialized from.
+///
+/// # JSON format
+///
+/// [`to_json`](Self::to_json) produces an object of this form, with one
+/// entry in `files` per output file, sorted by output path:
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "files": [
+///     {
+///       "git_stub_path": "openapi/api.json.gitstub",
+///       "commit": "0123456789abcdef0123456789abcdef01234567",
+///       "path": "openapi/api.json",
+///       "blob_id": "76543210fedcba9876543210fedcba9876543210",
+///       "size": 1234,
+///       "output_path": "out/openapi/api.json"
+///     }
+///   ]
+/// }
+/// ```
+///
+/// `version` is [`MANIFEST_VERSION`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub(crate) fn new(mut entries: Vec<ManifestEntry>) -> Self {
+        entries.sort_by(|a, b| a.output_path.cmp(&b.output_path));
+        Self { entries }
+    }
+
+    /// Returns the entries in the manifest, sorted by output path.
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+
+    /// Serializes the manifest as pretty-printed JSON, with a trailing
+    /// newline.
+    pub fn to_json(&self) -> String {
+        let json = ManifestJson {
+            version: MANIFEST_VERSION,
+            files: self
+                .entries
+                .iter()
+                .map(|entry| ManifestEntryJson {
+                    git_stub_path: entry.git_stub_path.as_str(),
+                    commit: entry.stub.commit().to_string(),
+                    path: entry.stub.path().as_str(),
+                    blob_id: &entry.blob_id,
+                    size: entry.size,
+                    output_path: entry.output_path.as_str(),
+                })
+                .collect(),
+        };
+        let mut out = serde_json::to_string_pretty(&json)
+            .expect("serializing strings and integers can't fail");
+        out.push('\n');
+        out
+    }
+
+    /// Atomically writes the manifest as JSON to `path`, creating parent
+    /// directories as needed.
+    pub fn write_json(
+        &self,
+        path: impl AsRef<Utf8Path>,
+    ) -> Result<(), WriteManifestError> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|error| {
+                WriteManifestError::CreateDir { path: parent.to_owned(), error }
+            })?;
+        }
+        atomic_write(path, self.to_json().as_bytes()).map_err(|error| {
+            WriteManifestError::Write { path: path.to_owned(), error }
+        })
+    }
+}
+
+/// A materialized git stub, as recorded in a [`Manifest`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    git_stub_path: Utf8PathBuf,
+    stub: GitStub,
+    blob_id: String,
+    size: u64,
+    output_path: Utf8PathBuf,
+}
+
+impl ManifestEntry {
+    pub(crate) fn new(file: MaterializedFile, contents: &[u8]) -> Self {
+        let MaterializedFile { git_stub_path, stub, output_path } = file;
+        Self {
+            git_stub_path,
+            blob_id: blob_id(stub.commit(), contents),
+            size: contents.len() as u64,
+            stub,
+            output_path,
+        }
+    }
+
+    /// Returns the path to the git stub file, relative to the repository
+    /// root.
+    pub fn git_stub_path(&self) -> &Utf8Path {
+        &self.git_stub_path
+    }
+
+    /// Returns the commit referenced by the git stub file.
+    pub fn commit(&self) -> GitCommitHash {
+        self.stub.commit()
+    }
+
+    /// Returns the path referenced by the git stub file.
+    ///
+    /// If the git stub is part of a chain, this is the path the git stub
+    /// file references directly, not the final one. The blob ID and size are
+    /// always those of the materialized contents.
+    pub fn path(&self) -> &Utf8Path {
+        self.stub.path()
+    }
+
+    /// Returns the Git blob ID of the materialized contents, as a hex
+    /// string.
+    ///
+    /// This is computed from the contents using the same hash function as
+    /// the commit (SHA-1 or SHA-256), so it matches the blob ID Git reports.
+    pub fn blob_id(&self) -> &str {
+        &self.blob_id
+    }
+
+    /// Returns the size of the materialized contents, in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the path to the materialized file.
+    pub fn output_path(&self) -> &Utf8Path {
+        &self.output_path
+    }
+}
+
+#[derive(Serialize)]
+struct ManifestJson<'a> {
+    version: u32,
+    files: Vec<ManifestEntryJson<'a>>,
+}
+
+#[derive(Serialize)]
+struct ManifestEntryJson<'a> {
+    git_stub_path: &'a str,
+    commit: String,
+    path: &'a str,
+    blob_id: &'a str,
+    size: u64,
+    output_path: &'a str,
+}
+
+/// Returns the Git blob ID of `contents`, using the hash function of
+/// `commit`.
+fn blob_id(commit: GitCommitHash, contents: &[u8]) -> String {
+    fn hash<D: Digest>(contents: &[u8]) -> String {
+        let mut hasher = D::new();
+        hasher.update(format!("blob {}\0", contents.len()));
+        hasher.update(contents);
+        hex::encode(hasher.finalize())
+    }
+
+    match commit {
+        GitCommitHash::Sha1(_) => hash::<Sha1>(contents),
+        GitCommitHash::Sha256(_) => hash::<Sha256>(contents),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blob_id() {
+        // Known values from `git hash-object` (and `git hash-object
+        // --object-format=sha256`) for "hello\n".
+        let sha1 = GitCommitHash::Sha1([0; 20]);
+        assert_eq!(
+            blob_id(sha1, b"hello\n"),
+            "ce013625030ba8dba906f756967f9e9ca394464a"
+        );
+        let sha256 = GitCommitHash::Sha256([0; 32]);
+        assert_eq!(
+            blob_id(sha256, b"hello\n"),
+            "2cf8d83d9ee29543b34a87727421fdecb7e3f3a183d337639025de576db9ebb4"
+        );
+    }
+}
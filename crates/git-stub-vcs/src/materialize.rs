@@ -2,12 +2,28 @@
 
 //! Materialization logic for git stubs.
 
-use crate::{MaterializeError, Vcs};
+use crate::{
+    AtomicWriteError, DEFAULT_MAX_CHAIN_DEPTH, DirectiveSink,
+    MaterializeAllError, MaterializeError, PruneError, ReadContentsError, Vcs,
+    WriteDepfileError, WriteModuleError, codegen,
+    state::{MaterializeState, STATE_FILE_NAME},
+};
+#[cfg(feature = "manifest")]
+use crate::{Manifest, ManifestEntry, ManifestError};
 use atomicwrites::AtomicFile;
 use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
 use fs_err as fs;
-use git_stub::GitStub;
-use std::io::Write;
+use git_stub::{GitCommitHash, GitStub};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Write},
+    num::NonZeroUsize,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
 
 /// Returns the first non-normal component in the path, if any.
 ///
@@ -34,6 +50,69 @@ fn check_path(path: &Utf8Path) -> Result<(), MaterializeError> {
     Ok(())
 }
 
+/// Returns `CARGO_MANIFEST_DIR` and the output directory within `OUT_DIR`.
+///
+/// Panics if either environment variable is not set.
+fn build_script_dirs() -> (Utf8PathBuf, Utf8PathBuf) {
+    let out_dir = std::env::var("OUT_DIR").expect(
+        "OUT_DIR is set \
+         (must be called from a Cargo build script)",
+    );
+    let out_dir = Utf8PathBuf::from(out_dir).join("git-stub-vcs");
+
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").expect(
+        "CARGO_MANIFEST_DIR is set \
+             (must be called from a Cargo build script)",
+    );
+    (Utf8PathBuf::from(manifest_dir), out_dir)
+}
+
+/// The result of materializing a git stub.
+///
+/// Returned by [`Materializer::materialize_with_status`] and
+/// [`Materializer::materialize_all`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Materialized {
+    output_path: Utf8PathBuf,
+    written: bool,
+}
+
+impl Materialized {
+    /// Returns the path to the materialized file.
+    pub fn output_path(&self) -> &Utf8Path {
+        &self.output_path
+    }
+
+    /// Returns whether the file was written.
+    ///
+    /// This is false if the file already had the referenced contents, in
+    /// which case it was left untouched.
+    pub fn written(&self) -> bool {
+        self.written
+    }
+
+    /// Consumes `self`, returning the path to the materialized file.
+    pub fn into_output_path(self) -> Utf8PathBuf {
+        self.output_path
+    }
+}
+
+/// A git stub materialized by a [`Materializer`], recorded for manifests,
+/// depfiles and Rust modules.
+#[derive(Clone, Debug)]
+pub(crate) struct MaterializedFile {
+    /// The git stub path, relative to the repository root.
+    pub(crate) git_stub_path: Utf8PathBuf,
+    /// The git stub.
+    #[cfg_attr(
+        not(feature = "manifest"),
+        expect(dead_code, reason = "only read for manifests")
+    )]
+    pub(crate) stub: GitStub,
+    /// The path to the materialized file.
+    pub(crate) output_path: Utf8PathBuf,
+}
+
 /// Materializes git stubs into actual file content.
 ///
 /// Reads `.gitstub` files, fetches the referenced content from Git history,
@@ -42,7 +121,19 @@ fn check_path(path: &Utf8Path) -> Result<(), MaterializeError> {
 pub struct Materializer {
     repo_root: Utf8PathBuf,
     output_dir: Utf8PathBuf,
-    emit_cargo_directives: bool,
+    directives: DirectiveSink,
+    warnings: bool,
+    max_chain_depth: usize,
+    max_concurrency: Option<NonZeroUsize>,
+    state: Option<Arc<Mutex<MaterializeState>>>,
+    /// Git stubs materialized so far (by this materializer or its clones),
+    /// keyed by output path.
+    materialized: Arc<Mutex<BTreeMap<Utf8PathBuf, MaterializedFile>>>,
+    /// Paths that [`prune`](Self::prune) keeps: the output paths of all git
+    /// stubs this materializer (or its clones) attempted to materialize,
+    /// including those that failed, and the other files it wrote.
+    kept_paths: Arc<Mutex<BTreeSet<Utf8PathBuf>>>,
+    git_stub_commit: Option<GitCommitHash>,
     vcs: Vcs,
 }
 
@@ -64,12 +155,7 @@ impl Materializer {
         let repo_root = repo_root.into();
         let vcs = Vcs::detect(&repo_root)?;
         Self::check_shallow(&vcs, &repo_root)?;
-        Ok(Materializer {
-            repo_root,
-            output_dir: output_dir.into(),
-            emit_cargo_directives: false,
-            vcs,
-        })
+        Ok(Self::from_parts(repo_root, output_dir.into(), vcs))
     }
 
     /// Creates a new materializer for use in Cargo build scripts.
@@ -77,7 +163,9 @@ impl Materializer {
     /// This constructor reads `OUT_DIR` from the environment for the output
     /// directory, and writes files to the `git-stub-vcs` directory
     /// within `OUT_DIR`. It also emits `cargo::rerun-if-changed` directives
-    /// for each materialized file.
+    /// for each materialized file (see
+    /// [`with_directive_sink`](Self::with_directive_sink)), and enables the
+    /// [state file](Self::with_state_file).
     ///
     /// `repo_root` is relative to `CARGO_MANIFEST_DIR` (the directory
     /// containing the crate's `Cargo.toml`), and is typically a relative
@@ -91,27 +179,98 @@ impl Materializer {
     pub fn for_build_script(
         repo_root: impl Into<Utf8PathBuf>,
     ) -> Result<Self, MaterializeError> {
-        let out_dir = std::env::var("OUT_DIR").expect(
-            "OUT_DIR is set \
-             (must be called from a Cargo build script)",
-        );
-        let out_dir = Utf8PathBuf::from(out_dir).join("git-stub-vcs");
-
-        let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").expect(
-            "CARGO_MANIFEST_DIR is set \
-                 (must be called from a Cargo build script)",
-        );
-        let manifest_dir = Utf8PathBuf::from(manifest_dir);
+        let (manifest_dir, out_dir) = build_script_dirs();
         let repo_root = manifest_dir.join(repo_root.into());
 
         let vcs = Vcs::detect(&repo_root)?;
         Self::check_shallow(&vcs, &repo_root)?;
-        Ok(Materializer {
+        Ok(Self::from_parts(repo_root, out_dir, vcs)
+            .with_directive_sink(DirectiveSink::stdout())
+            .with_state_file(true)
+            .with_warnings(true))
+    }
+
+    /// Creates a new materializer for general use, discovering the
+    /// repository root by walking up from `start`.
+    ///
+    /// Like [`standard`](Self::standard), but uses [`Vcs::discover`] rather
+    /// than requiring the exact repository root. Use
+    /// [`repo_root`](Self::repo_root) to get the discovered root.
+    pub fn discover(
+        start: impl AsRef<Utf8Path>,
+        output_dir: impl Into<Utf8PathBuf>,
+    ) -> Result<Self, MaterializeError> {
+        let (vcs, repo_root) = Vcs::discover(start.as_ref())?;
+        Self::check_shallow(&vcs, &repo_root)?;
+        Ok(Self::from_parts(repo_root, output_dir.into(), vcs))
+    }
+
+    /// Creates a new materializer for use in Cargo build scripts,
+    /// discovering the repository root by walking up from
+    /// `CARGO_MANIFEST_DIR`.
+    ///
+    /// Like [`for_build_script`](Self::for_build_script), but doesn't need
+    /// to be updated if the crate is moved within the repository.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `OUT_DIR` or `CARGO_MANIFEST_DIR` environment variables
+    /// are not set.
+    pub fn discover_for_build_script() -> Result<Self, MaterializeError> {
+        let (manifest_dir, out_dir) = build_script_dirs();
+        let (vcs, repo_root) = Vcs::discover(&manifest_dir)?;
+        Self::check_shallow(&vcs, &repo_root)?;
+        Ok(Self::from_parts(repo_root, out_dir, vcs)
+            .with_directive_sink(DirectiveSink::stdout())
+            .with_state_file(true)
+            .with_warnings(true))
+    }
+
+    /// Creates a new materializer for a bare Git repository, reading git
+    /// stub files from `revision` rather than from the filesystem.
+    ///
+    /// `git_dir` is the bare repository (or the `.git` directory of a
+    /// repository with a working tree), and is treated as relative to the
+    /// current working directory. `revision` (for example, `HEAD` or a
+    /// release tag) is resolved to a commit once, at construction time.
+    ///
+    /// `output_dir` is an output directory relative to the current working
+    /// directory.
+    ///
+    /// Returns an error if `git_dir` is not a Git directory, if `revision`
+    /// doesn't exist, or if the repository is a shallow clone.
+    pub fn bare(
+        git_dir: impl Into<Utf8PathBuf>,
+        revision: &str,
+        output_dir: impl Into<Utf8PathBuf>,
+    ) -> Result<Self, MaterializeError> {
+        let repo_root = git_dir.into();
+        let vcs = Vcs::detect(&repo_root)?;
+        Self::check_shallow(&vcs, &repo_root)?;
+        Self::from_parts(repo_root, output_dir.into(), vcs)
+            .with_git_stub_revision(revision)
+    }
+
+    /// Creates a materializer with default settings: directives discarded,
+    /// and no warnings or state file.
+    fn from_parts(
+        repo_root: Utf8PathBuf,
+        output_dir: Utf8PathBuf,
+        vcs: Vcs,
+    ) -> Self {
+        Materializer {
             repo_root,
-            output_dir: out_dir,
-            emit_cargo_directives: true,
+            output_dir,
+            directives: DirectiveSink::discard(),
+            warnings: false,
+            max_chain_depth: DEFAULT_MAX_CHAIN_DEPTH,
+            max_concurrency: None,
+            state: None,
+            materialized: Arc::default(),
+            kept_paths: Arc::default(),
+            git_stub_commit: None,
             vcs,
-        })
+        }
     }
 
     /// Overrides the detected VCS.
@@ -127,11 +286,156 @@ impl Materializer {
         Ok(self)
     }
 
+    /// Sets the maximum number of chained git stubs to follow.
+    ///
+    /// If a git stub references another `.gitstub` file (for example, after
+    /// archiving a directory that already contained git stubs), the
+    /// materializer follows the chain to the final contents. The default is
+    /// [`DEFAULT_MAX_CHAIN_DEPTH`]. A depth of 0 disallows chains.
+    ///
+    /// See [`Vcs::read_git_stub_chain`] for details.
+    pub fn with_max_chain_depth(mut self, max_chain_depth: usize) -> Self {
+        self.max_chain_depth = max_chain_depth;
+        self
+    }
+
+    /// Sets the maximum number of git stubs that
+    /// [`materialize_all`](Self::materialize_all) materializes at once.
+    ///
+    /// The default is the available parallelism reported by
+    /// [`std::thread::available_parallelism`].
+    pub fn with_max_concurrency(
+        mut self,
+        max_concurrency: NonZeroUsize,
+    ) -> Self {
+        self.max_concurrency = Some(max_concurrency);
+        self
+    }
+
+    /// Sets whether to maintain a state file in the output directory, to
+    /// skip work for git stubs that haven't changed.
+    ///
+    /// The state file (`.git-stub-state`) records, for each output file, the
+    /// git stub it was materialized from and a digest of the contents that
+    /// were written. If a git stub file is unchanged and the output file
+    /// still has those contents, the VCS isn't run at all.
+    ///
+    /// The state file is only a cache: if it can't be read or written, git
+    /// stubs are materialized as if it were absent. It is enabled by default
+    /// for [`for_build_script`](Self::for_build_script) and
+    /// [`discover_for_build_script`](Self::discover_for_build_script), and
+    /// disabled otherwise.
+    pub fn with_state_file(mut self, enabled: bool) -> Self {
+        self.state = if enabled { load_state(&self.output_dir) } else { None };
+        self
+    }
+
+    /// Sends Cargo build script directives to `sink`.
+    ///
+    /// The materializer emits `cargo::rerun-if-changed` for each git stub
+    /// file it reads, and `cargo::rerun-if-env-changed` for the environment
+    /// variables that select the VCS binary (`GIT` and `JJ`). By default,
+    /// these are printed to standard output for
+    /// [`for_build_script`](Self::for_build_script) and
+    /// [`discover_for_build_script`](Self::discover_for_build_script), and
+    /// discarded otherwise.
+    pub fn with_directive_sink(mut self, sink: DirectiveSink) -> Self {
+        self.directives = sink;
+        self
+    }
+
+    /// Sets whether to emit `cargo::warning` directives for suspicious git
+    /// stubs.
+    ///
+    /// Problems such as these don't stop a build, so they otherwise tend to
+    /// go unnoticed until they cause failures later on, in CI. If enabled,
+    /// a warning is emitted to the [directive
+    /// sink](Self::with_directive_sink) when a git stub:
+    ///
+    /// - is not in canonical form (see [`GitStub::needs_rewrite`]);
+    /// - references a commit that isn't reachable from the checked-out
+    ///   commit (`HEAD` for Git, or `@` for Jujutsu), or from the [git stub
+    ///   revision](Self::with_git_stub_revision) if set, and so may be lost
+    ///   to garbage collection; or
+    /// - references a file that is also tracked in the working tree, so that
+    ///   the git stub is likely redundant. (This isn't checked when reading
+    ///   git stub files from a revision.)
+    ///
+    /// Checking reachability and tracked files runs two extra VCS commands
+    /// per git stub, so these checks are skipped if the [state
+    /// file](Self::with_state_file) shows that the git stub is unchanged
+    /// since it was last materialized. Warnings are enabled by default for
+    /// [`for_build_script`](Self::for_build_script) and
+    /// [`discover_for_build_script`](Self::discover_for_build_script), and
+    /// disabled otherwise.
+    pub fn with_warnings(mut self, enabled: bool) -> Self {
+        self.warnings = enabled;
+        self
+    }
+
+    /// Sets the maximum time each VCS command may run for.
+    ///
+    /// See [`Vcs::with_timeout`] for details. To also set a cancellation
+    /// token, configure the [`Vcs`] and pass it to
+    /// [`with_vcs`](Self::with_vcs).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.vcs = self.vcs.with_timeout(timeout);
+        self
+    }
+
+    /// Reads git stub files from `revision` rather than from the filesystem.
+    ///
+    /// Use this in release tooling to materialize exactly what a revision
+    /// (for example, `HEAD` or a release tag) referenced, regardless of the
+    /// current checkout. `revision` is resolved to a commit immediately, so
+    /// later changes to the ref don't affect this materializer.
+    ///
+    /// In this mode, `cargo::rerun-if-changed` directives are not emitted for
+    /// git stub files, since their contents at a commit can't change.
+    ///
+    /// Returns an error if `revision` doesn't exist.
+    pub fn with_git_stub_revision(
+        mut self,
+        revision: &str,
+    ) -> Result<Self, MaterializeError> {
+        let commit =
+            Self::resolve_revision(&self.vcs, &self.repo_root, revision)?;
+        self.git_stub_commit = Some(commit);
+        Ok(self)
+    }
+
+    /// Returns the repository root.
+    pub fn repo_root(&self) -> &Utf8Path {
+        &self.repo_root
+    }
+
     /// Returns the VCS that will be used for materialization.
     pub fn vcs(&self) -> &Vcs {
         &self.vcs
     }
 
+    /// Returns the commit that git stub files are read from, or `None` if
+    /// they are read from the filesystem.
+    pub fn git_stub_commit(&self) -> Option<GitCommitHash> {
+        self.git_stub_commit
+    }
+
+    /// Resolves `revision` to a single commit.
+    fn resolve_revision(
+        vcs: &Vcs,
+        repo_root: &Utf8Path,
+        revision: &str,
+    ) -> Result<GitCommitHash, MaterializeError> {
+        vcs.resolve_commit(repo_root, revision)
+            .map_err(|error| MaterializeError::ResolveRevision {
+                revision: revision.to_owned(),
+                error,
+            })?
+            .ok_or_else(|| MaterializeError::RevisionNotFound {
+                revision: revision.to_owned(),
+            })
+    }
+
     /// Checks whether the repository is a shallow clone and returns an
     /// error if so. Called once at construction time rather than on every
     /// `materialize()` call.
@@ -175,29 +479,278 @@ impl Materializer {
         &self,
         git_stub_path: impl AsRef<Utf8Path>,
     ) -> Result<Utf8PathBuf, MaterializeError> {
+        self.materialize_with_status(git_stub_path)
+            .map(Materialized::into_output_path)
+    }
+
+    /// Materializes a git stub, reporting whether the output file was
+    /// written.
+    ///
+    /// Like [`materialize`](Self::materialize). If the output file already
+    /// has the referenced contents, it is left untouched (preserving its
+    /// modification time), and [`Materialized::written`] returns false.
+    pub fn materialize_with_status(
+        &self,
+        git_stub_path: impl AsRef<Utf8Path>,
+    ) -> Result<Materialized, MaterializeError> {
         let git_stub_path = git_stub_path.as_ref();
+        check_git_stub_path(git_stub_path)?;
 
-        check_path(git_stub_path)?;
+        // Preserve directory structure, stripping only the .gitstub
+        // extension.
+        let output_path =
+            self.output_dir.join(git_stub_path.with_extension(""));
+        let written = self.materialize_inner(git_stub_path, &output_path);
+        self.save_state();
+        Ok(Materialized { output_path, written: written? })
+    }
+
+    /// Materializes several git stubs concurrently.
+    ///
+    /// Each git stub is materialized as with
+    /// [`materialize`](Self::materialize), using up to the number of threads
+    /// set with [`with_max_concurrency`](Self::with_max_concurrency).
+    ///
+    /// Returns the materialized files, in the same order as
+    /// `git_stub_paths`.
+    ///
+    /// # Errors
+    ///
+    /// If two git stubs would be materialized to the same output path, returns
+    /// [`MaterializeAllError::DuplicateOutput`] without materializing any git
+    /// stubs.
+    ///
+    /// Otherwise, a failure to materialize one git stub doesn't stop others
+    /// from being materialized. If any fail, returns
+    /// [`MaterializeAllError::Failed`] with every error, in the same order as
+    /// `git_stub_paths` (regardless of the order in which git stubs were
+    /// materialized).
+    pub fn materialize_all<I, P>(
+        &self,
+        git_stub_paths: I,
+    ) -> Result<Vec<Materialized>, MaterializeAllError>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Utf8Path>,
+    {
+        let git_stub_paths: Vec<Utf8PathBuf> = git_stub_paths
+            .into_iter()
+            .map(|path| path.as_ref().to_owned())
+            .collect();
 
-        if git_stub_path.extension() != Some("gitstub") {
-            return Err(MaterializeError::NotGitStub {
-                path: git_stub_path.to_owned(),
+        // Validate all paths, and check that output paths are distinct,
+        // before materializing anything.
+        let mut errors = BTreeMap::new();
+        let mut jobs = Vec::new();
+        let mut by_output = BTreeMap::<_, Vec<_>>::new();
+        for (index, git_stub_path) in git_stub_paths.iter().enumerate() {
+            match check_git_stub_path(git_stub_path) {
+                Ok(()) => {
+                    let output_path =
+                        self.output_dir.join(git_stub_path.with_extension(""));
+                    by_output
+                        .entry(output_path.clone())
+                        .or_default()
+                        .push(git_stub_path.clone());
+                    jobs.push((index, output_path));
+                }
+                Err(error) => {
+                    errors.insert(index, error);
+                }
+            }
+        }
+        if let Some((output_path, git_stub_paths)) =
+            by_output.into_iter().find(|(_, paths)| paths.len() > 1)
+        {
+            return Err(MaterializeAllError::DuplicateOutput {
+                output_path,
+                git_stub_paths,
             });
         }
 
-        // Preserve directory structure, stripping only the .gitstub
-        // extension.
+        let max_concurrency = self.max_concurrency.unwrap_or_else(|| {
+            std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN)
+        });
+        let workers = max_concurrency.get().min(jobs.len());
+        let next = AtomicUsize::new(0);
+        let results = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = Vec::new();
+                        while let Some((index, output_path)) =
+                            jobs.get(next.fetch_add(1, Ordering::Relaxed))
+                        {
+                            let result = self.materialize_inner(
+                                &git_stub_paths[*index],
+                                output_path,
+                            );
+                            results.push((*index, result));
+                        }
+                        results
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| {
+                    handle.join().unwrap_or_else(|payload| {
+                        std::panic::resume_unwind(payload)
+                    })
+                })
+                .collect::<Vec<_>>()
+        });
+        self.save_state();
+        let mut written = vec![false; git_stub_paths.len()];
+        for (index, result) in results {
+            match result {
+                Ok(w) => written[index] = w,
+                Err(error) => {
+                    errors.insert(index, error);
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(jobs
+                .into_iter()
+                .map(|(index, output_path)| Materialized {
+                    output_path,
+                    written: written[index],
+                })
+                .collect())
+        } else {
+            Err(MaterializeAllError::Failed {
+                errors: errors
+                    .into_iter()
+                    .map(|(index, error)| {
+                        (git_stub_paths[index].clone(), error)
+                    })
+                    .collect(),
+            })
+        }
+    }
+
+    /// Asynchronously materializes a git stub.
+    ///
+    /// This is the asynchronous version of
+    /// [`materialize`](Self::materialize), using Tokio for process spawning
+    /// and file I/O. It must be called from within a Tokio runtime, with the
+    /// time driver enabled if a timeout or cancellation token is set.
+    #[cfg(feature = "tokio")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "tokio")))]
+    pub async fn materialize_async(
+        &self,
+        git_stub_path: impl AsRef<Utf8Path>,
+    ) -> Result<Utf8PathBuf, MaterializeError> {
+        self.materialize_with_status_async(git_stub_path)
+            .await
+            .map(Materialized::into_output_path)
+    }
+
+    /// Asynchronously materializes a git stub, reporting whether the output
+    /// file was written.
+    ///
+    /// This is the asynchronous version of
+    /// [`materialize_with_status`](Self::materialize_with_status), with the
+    /// same requirements as [`materialize_async`](Self::materialize_async).
+    #[cfg(feature = "tokio")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "tokio")))]
+    pub async fn materialize_with_status_async(
+        &self,
+        git_stub_path: impl AsRef<Utf8Path>,
+    ) -> Result<Materialized, MaterializeError> {
+        let git_stub_path = git_stub_path.as_ref();
+        check_git_stub_path(git_stub_path)?;
+
         let output_path =
             self.output_dir.join(git_stub_path.with_extension(""));
-        self.materialize_inner(git_stub_path, &output_path)?;
-        Ok(output_path)
+        lock(&self.kept_paths).insert(output_path.clone());
+        let full_git_stub_path = self.repo_root.join(git_stub_path);
+        self.directives.emit_rerun_if_env_changed();
+        let git_stub_contents = match self.git_stub_commit {
+            None => {
+                self.directives.emit_rerun_if_changed(&full_git_stub_path);
+                tokio::fs::read(&full_git_stub_path).await.map_err(|error| {
+                    MaterializeError::ReadGitStub {
+                        path: full_git_stub_path.clone(),
+                        error,
+                    }
+                })?
+            }
+            Some(commit) => {
+                let location = git_stub_location(git_stub_path, commit)?;
+                self.vcs
+                    .read_git_stub_contents_async(&location, &self.repo_root)
+                    .await
+                    .map_err(|error| {
+                        read_at_commit_error(git_stub_path, commit, error)
+                    })?
+            }
+        };
+        let git_stub = parse_git_stub(&full_git_stub_path, &git_stub_contents)?;
+        for warning in self.stub_warnings(&full_git_stub_path, &git_stub) {
+            self.directives.emit_warning(&warning);
+        }
+
+        // Checking freshness reads and hashes the output, so do it from the
+        // blocking pool.
+        let materializer = self.clone();
+        let (path, stub) = (output_path.clone(), git_stub.clone());
+        let stub_path = git_stub_path.to_owned();
+        let fresh = spawn_blocking(git_stub_path, move || {
+            materializer.is_fresh(&path, &stub_path, &stub)
+        })
+        .await?;
+        if fresh {
+            self.record_materialized(&output_path, git_stub_path, &git_stub);
+            return Ok(Materialized { output_path, written: false });
+        }
+
+        if self.warnings {
+            // These checks run the VCS synchronously.
+            let materializer = self.clone();
+            let (path, stub) = (full_git_stub_path.clone(), git_stub.clone());
+            let warnings = spawn_blocking(git_stub_path, move || {
+                materializer.vcs_warnings(&path, &stub)
+            })
+            .await?;
+            for warning in warnings {
+                self.directives.emit_warning(&warning);
+            }
+        }
+
+        let content = self
+            .vcs
+            .read_git_stub_chain_async(
+                &git_stub,
+                &self.repo_root,
+                self.max_chain_depth,
+            )
+            .await?
+            .into_contents();
+
+        // atomicwrites is synchronous, so write (and update the state file)
+        // from the blocking pool.
+        let materializer = self.clone();
+        let path = output_path.clone();
+        let stub_path = git_stub_path.to_owned();
+        let written = spawn_blocking(git_stub_path, move || {
+            let written = write_output(&path, &content)?;
+            materializer.record_state(&path, &stub_path, &git_stub, &content);
+            materializer.save_state();
+            materializer.record_materialized(&path, &stub_path, &git_stub);
+            Ok::<_, MaterializeError>(written)
+        })
+        .await??;
+        Ok(Materialized { output_path, written })
     }
 
     /// Materializes a git stub to a specific path.
     ///
     /// Like [`materialize`](Self::materialize), but writes to `output_path`
     /// (relative to the output directory, or absolute) instead of deriving the
-    /// path from the Git stub file name.
+    /// path from the git stub file name.
     ///
     /// `git_stub_path` is relative to the repository root.
     pub fn materialize_to(
@@ -207,69 +760,710 @@ impl Materializer {
     ) -> Result<(), MaterializeError> {
         let git_stub_path = git_stub_path.as_ref();
         let output_path = output_path.as_ref();
+        check_git_stub_path(git_stub_path)?;
 
-        check_path(git_stub_path)?;
+        let output_path = self.output_dir.join(output_path);
+        let written = self.materialize_inner(git_stub_path, &output_path);
+        self.save_state();
+        written.map(|_| ())
+    }
 
-        if git_stub_path.extension() != Some("gitstub") {
-            return Err(MaterializeError::NotGitStub {
-                path: git_stub_path.to_owned(),
-            });
+    /// Removes files in the output directory that weren't materialized by
+    /// this materializer.
+    ///
+    /// When git stubs are renamed or deleted, their old outputs are otherwise
+    /// left behind. Call this after materializing every git stub that should
+    /// be present: any other file in the output directory is removed, along
+    /// with any directories left empty. Files outside the output directory
+    /// are never touched.
+    ///
+    /// The following files are kept:
+    ///
+    /// * Files materialized by this materializer or its clones.
+    /// * Outputs of git stubs that this materializer attempted to
+    ///   materialize, but failed, so that a transient error doesn't remove
+    ///   the previous output.
+    /// * Depfiles and Rust modules written by
+    ///   [`write_depfile`](Self::write_depfile) and
+    ///   [`write_rust_module`](Self::write_rust_module).
+    /// * The [state file](Self::with_state_file).
+    ///
+    /// Other files written to the output directory, such as manifests, are
+    /// removed: write them after pruning, or outside the output directory.
+    ///
+    /// Returns the paths of the removed files, in sorted order. If the output
+    /// directory doesn't exist, there is nothing to remove.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// // In build.rs:
+    /// let materializer = git_stub_vcs::Materializer::for_build_script("../..")
+    ///     .expect("VCS detected at repo root");
+    /// let paths = ["openapi/my-api/my-api-1.0.0-abc123.json.gitstub"];
+    /// materializer
+    ///     .materialize_all(paths)
+    ///     .expect("materialized successfully");
+    /// materializer.prune().expect("pruned successfully");
+    /// ```
+    pub fn prune(&self) -> Result<Vec<Utf8PathBuf>, PruneError> {
+        let keep: BTreeSet<_> = lock(&self.kept_paths)
+            .iter()
+            .map(|path| absolute_path(path))
+            .collect();
+        let mut removed = Vec::new();
+        match std::fs::symlink_metadata(&self.output_dir) {
+            Ok(meta) if meta.is_dir() => {
+                prune_dir(&self.output_dir, true, &keep, &mut removed)?;
+            }
+            Ok(_) => {}
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => {
+                return Err(PruneError::ReadDir {
+                    path: self.output_dir.clone(),
+                    error,
+                });
+            }
         }
 
-        let output_path = self.output_dir.join(output_path);
-        self.materialize_inner(git_stub_path, &output_path)
+        if let Some(state) = &self.state {
+            let mut state = lock(state);
+            for path in &removed {
+                state.forget(&self.output_dir, path);
+            }
+            state.save(&self.output_dir);
+        }
+        removed.sort();
+        Ok(removed)
+    }
+
+    /// Returns a manifest of the git stubs materialized so far by this
+    /// materializer (or its clones).
+    ///
+    /// Git stubs that failed to materialize aren't included. If the same
+    /// output path was materialized more than once, only the last git stub
+    /// materialized to it is included.
+    ///
+    /// Blob IDs and sizes are computed from the materialized files when this
+    /// is called, rather than while materializing. Returns an error if a
+    /// materialized file can't be read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// // In build.rs:
+    /// let materializer = git_stub_vcs::Materializer::for_build_script("../..")
+    ///     .expect("VCS detected at repo root");
+    /// materializer
+    ///     .materialize("openapi/my-api/my-api-1.0.0-abc123.json.gitstub")
+    ///     .expect("materialized successfully");
+    /// let out_dir = std::env::var("OUT_DIR").unwrap();
+    /// materializer
+    ///     .manifest()
+    ///     .expect("read materialized files")
+    ///     .write_json(format!("{out_dir}/git-stub-manifest.json"))
+    ///     .expect("wrote manifest");
+    /// ```
+    #[cfg(feature = "manifest")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "manifest")))]
+    pub fn manifest(&self) -> Result<Manifest, ManifestError> {
+        let files: Vec<_> =
+            lock(&self.materialized).values().cloned().collect();
+        let entries = files
+            .into_iter()
+            .map(|file| {
+                let contents =
+                    fs::read(&file.output_path).map_err(|error| {
+                        ManifestError::ReadOutput {
+                            path: file.output_path.clone(),
+                            error,
+                        }
+                    })?;
+                Ok(ManifestEntry::new(file, &contents))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Manifest::new(entries))
+    }
+
+    /// Writes a Makefile-style depfile to `path`, for build systems other
+    /// than Cargo.
+    ///
+    /// Build systems such as Make, Ninja, and Buck2 don't understand the
+    /// `cargo::rerun-if-changed` directives emitted in build scripts, but
+    /// can read depfiles. The depfile has one rule per file materialized so
+    /// far by this materializer (or its clones), sorted by output path, in
+    /// which the output file depends on its git stub file:
+    ///
+    /// ```text
+    /// out/openapi/api.json: repo/openapi/api.json.gitstub
+    /// ```
+    ///
+    /// Spaces, `#` and `$` in paths are escaped, along with any backslashes
+    /// before a space or `#`. If git stub files are read
+    /// from a [revision](Self::with_git_stub_revision), they can't change,
+    /// so outputs are listed without any dependencies.
+    ///
+    /// Returns an error if a path can't be represented in a depfile: if it
+    /// contains a newline, contains a colon (other than in a Windows prefix
+    /// such as `C:`), or ends with a backslash.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// let materializer =
+    ///     git_stub_vcs::Materializer::standard("../..", "out/git-stubs")
+    ///         .expect("VCS detected at repo root");
+    /// materializer
+    ///     .materialize("openapi/my-api/my-api-1.0.0-abc123.json.gitstub")
+    ///     .expect("materialized successfully");
+    /// materializer
+    ///     .write_depfile("out/git-stubs.d")
+    ///     .expect("wrote depfile");
+    /// ```
+    pub fn write_depfile(
+        &self,
+        path: impl AsRef<Utf8Path>,
+    ) -> Result<(), WriteDepfileError> {
+        let path = path.as_ref();
+        let mut contents = String::new();
+        for file in lock(&self.materialized).values() {
+            contents.push_str(&escape_depfile_path(&file.output_path)?);
+            contents.push(':');
+            if self.git_stub_commit.is_none() {
+                let git_stub_path = self.repo_root.join(&file.git_stub_path);
+                contents.push(' ');
+                contents.push_str(&escape_depfile_path(&git_stub_path)?);
+            }
+            contents.push('\n');
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|error| {
+                WriteDepfileError::CreateDir { path: parent.to_owned(), error }
+            })?;
+        }
+        atomic_write(path, contents.as_bytes()).map_err(|error| {
+            WriteDepfileError::Write { path: path.to_owned(), error }
+        })?;
+        lock(&self.kept_paths).insert(path.to_owned());
+        Ok(())
+    }
+
+    /// Writes a Rust module to `path` describing the files materialized so
+    /// far by this materializer (or its clones), and emits a
+    /// `cargo::rustc-env` directive for each one.
+    ///
+    /// Each file is identified by a name derived from its git stub path:
+    /// the path without the `.gitstub` extension, in upper case, with
+    /// characters other than ASCII letters and digits replaced by
+    /// underscores (and a leading underscore if it doesn't start with a
+    /// letter). For example, `openapi/api-v1.json.gitstub` is named
+    /// `OPENAPI_API_V1_JSON`. For each file, the module defines:
+    ///
+    /// - `<NAME>_PATH`, a `&str` with the absolute path to the file; and
+    /// - `<NAME>`, a `&[u8]` with the file's contents, via `include_bytes!`.
+    ///
+    /// The environment variable `GIT_STUB_<NAME>` is also set to the
+    /// absolute path to the file, for use with `env!`, through the
+    /// [directive sink](Self::with_directive_sink).
+    ///
+    /// In build scripts, use
+    /// [`write_build_script_module`](Self::write_build_script_module) to
+    /// write the module to `OUT_DIR/git_stubs.rs`.
+    ///
+    /// Returns an error if two materialized files would have the same name
+    /// (for example, if the same git stub was materialized to two paths with
+    /// [`materialize_to`](Self::materialize_to)).
+    pub fn write_rust_module(
+        &self,
+        path: impl AsRef<Utf8Path>,
+    ) -> Result<(), WriteModuleError> {
+        let path = path.as_ref();
+        let items = {
+            let materialized = lock(&self.materialized);
+            codegen::module_items(materialized.values(), |file| {
+                absolute_path(&file.output_path)
+            })?
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|error| {
+                WriteModuleError::CreateDir { path: parent.to_owned(), error }
+            })?;
+        }
+        let source = codegen::module_source(&items);
+        // Only rewrite the module if it changed, so that crates including it
+        // aren't needlessly rebuilt.
+        if !has_contents(path, source.as_bytes()) {
+            atomic_write(path, source.as_bytes()).map_err(|error| {
+                WriteModuleError::Write { path: path.to_owned(), error }
+            })?;
+        }
+        lock(&self.kept_paths).insert(path.to_owned());
+
+        for item in &items {
+            self.directives.emit_rustc_env(
+                &codegen::env_var_name(&item.name),
+                item.output_path.as_str(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Writes a Rust module to `OUT_DIR/git_stubs.rs` describing the files
+    /// materialized so far, for use in build scripts.
+    ///
+    /// See [`write_rust_module`](Self::write_rust_module) for the contents
+    /// of the module. Returns the path to the module.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `OUT_DIR` environment variable is not set.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// // In build.rs:
+    /// let materializer = git_stub_vcs::Materializer::for_build_script("../..")
+    ///     .expect("VCS detected at repo root");
+    /// materializer
+    ///     .materialize_all(["openapi/my-api.json.gitstub"])
+    ///     .expect("materialized successfully");
+    /// materializer.write_build_script_module().expect("wrote module");
+    /// ```
+    ///
+    /// Then, in the crate:
+    ///
+    /// ```ignore
+    /// include!(concat!(env!("OUT_DIR"), "/git_stubs.rs"));
+    ///
+    /// fn spec() -> &'static [u8] {
+    ///     OPENAPI_MY_API_JSON
+    /// }
+    /// ```
+    pub fn write_build_script_module(
+        &self,
+    ) -> Result<Utf8PathBuf, WriteModuleError> {
+        let out_dir = std::env::var("OUT_DIR").expect(
+            "OUT_DIR is set \
+             (must be called from a Cargo build script)",
+        );
+        let path = Utf8PathBuf::from(out_dir).join("git_stubs.rs");
+        self.write_rust_module(&path)?;
+        Ok(path)
+    }
+
+    /// Records that `git_stub` was materialized to `output_path`.
+    fn record_materialized(
+        &self,
+        output_path: &Utf8Path,
+        git_stub_path: &Utf8Path,
+        git_stub: &GitStub,
+    ) {
+        let file = MaterializedFile {
+            git_stub_path: git_stub_path.to_owned(),
+            stub: git_stub.clone(),
+            output_path: output_path.to_owned(),
+        };
+        lock(&self.materialized).insert(output_path.to_owned(), file);
+    }
+
+    /// Returns whether the state file shows that `git_stub` was already
+    /// materialized to `output_path`, with its contents unchanged since.
+    fn is_fresh(
+        &self,
+        output_path: &Utf8Path,
+        git_stub_path: &Utf8Path,
+        git_stub: &GitStub,
+    ) -> bool {
+        self.state.as_ref().is_some_and(|state| {
+            lock(state).is_fresh(
+                &self.output_dir,
+                output_path,
+                git_stub_path,
+                git_stub,
+            )
+        })
+    }
+
+    /// Records in the state file that `git_stub` was materialized to
+    /// `output_path` with `content`.
+    fn record_state(
+        &self,
+        output_path: &Utf8Path,
+        git_stub_path: &Utf8Path,
+        git_stub: &GitStub,
+        content: &[u8],
+    ) {
+        if let Some(state) = &self.state {
+            lock(state).record(
+                &self.output_dir,
+                output_path,
+                git_stub_path,
+                git_stub,
+                content,
+            );
+        }
+    }
+
+    /// Writes the state file, if enabled and changed.
+    fn save_state(&self) {
+        if let Some(state) = &self.state {
+            lock(state).save(&self.output_dir);
+        }
+    }
+
+    /// Returns warnings about the contents of the git stub file at
+    /// `full_git_stub_path`, if [warnings](Self::with_warnings) are enabled.
+    fn stub_warnings(
+        &self,
+        full_git_stub_path: &Utf8Path,
+        git_stub: &GitStub,
+    ) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.warnings && git_stub.needs_rewrite() {
+            warnings.push(format!(
+                "git stub {full_git_stub_path} is not in canonical form \
+                 (it should contain `{git_stub}` followed by a newline)"
+            ));
+        }
+        warnings
+    }
+
+    /// Returns warnings about the commit and path referenced by `git_stub`,
+    /// which require running the VCS, if [warnings](Self::with_warnings) are
+    /// enabled.
+    ///
+    /// These are only checked when the referenced contents are read, not
+    /// when the state file shows that the output is fresh.
+    fn vcs_warnings(
+        &self,
+        full_git_stub_path: &Utf8Path,
+        git_stub: &GitStub,
+    ) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if !self.warnings {
+            return warnings;
+        }
+
+        let commit = git_stub.commit();
+        let revision = match self.git_stub_commit {
+            Some(git_stub_commit) => git_stub_commit.to_string(),
+            None => self.vcs.head_revision().to_owned(),
+        };
+        // If the commit doesn't exist, this fails, and reading the contents
+        // reports a better error.
+        if let Ok(false) =
+            self.vcs.is_ancestor(&self.repo_root, commit, &revision)
+        {
+            warnings.push(format!(
+                "git stub {full_git_stub_path} references commit {commit}, \
+                 which is not reachable from {revision} and may be lost to \
+                 garbage collection"
+            ));
+        }
+
+        if self.git_stub_commit.is_none()
+            && matches!(
+                self.vcs.is_tracked(&self.repo_root, git_stub.path()),
+                Ok(true)
+            )
+        {
+            warnings.push(format!(
+                "git stub {full_git_stub_path} references {}, which is also \
+                 tracked in the working tree",
+                git_stub.path()
+            ));
+        }
+        warnings
     }
 
     /// Assumes `git_stub_path` has already been validated to have a
     /// `.gitstub` extension.
+    ///
+    /// Returns whether the output file was written.
     fn materialize_inner(
         &self,
         git_stub_path: &Utf8Path,
         output_path: &Utf8Path,
-    ) -> Result<(), MaterializeError> {
+    ) -> Result<bool, MaterializeError> {
+        lock(&self.kept_paths).insert(output_path.to_owned());
         let full_git_stub_path = self.repo_root.join(git_stub_path);
+        self.directives.emit_rerun_if_env_changed();
 
-        if self.emit_cargo_directives {
-            println!("cargo::rerun-if-changed={}", full_git_stub_path);
+        let git_stub_contents = match self.git_stub_commit {
+            None => {
+                self.directives.emit_rerun_if_changed(&full_git_stub_path);
+                fs::read(&full_git_stub_path).map_err(|error| {
+                    MaterializeError::ReadGitStub {
+                        path: full_git_stub_path.clone(),
+                        error,
+                    }
+                })?
+            }
+            Some(commit) => {
+                // Reading a file at a commit is exactly what a git stub
+                // describes.
+                let location = git_stub_location(git_stub_path, commit)?;
+                self.vcs
+                    .read_git_stub_contents(&location, &self.repo_root)
+                    .map_err(|error| {
+                        read_at_commit_error(git_stub_path, commit, error)
+                    })?
+            }
+        };
+        let git_stub = parse_git_stub(&full_git_stub_path, &git_stub_contents)?;
+        for warning in self.stub_warnings(&full_git_stub_path, &git_stub) {
+            self.directives.emit_warning(&warning);
+        }
+        if self.is_fresh(output_path, git_stub_path, &git_stub) {
+            self.record_materialized(output_path, git_stub_path, &git_stub);
+            return Ok(false);
+        }
+        for warning in self.vcs_warnings(&full_git_stub_path, &git_stub) {
+            self.directives.emit_warning(&warning);
         }
 
-        let git_stub_contents = fs::read_to_string(&full_git_stub_path)
-            .map_err(|error| MaterializeError::ReadGitStub {
-                path: full_git_stub_path.clone(),
-                error,
-            })?;
+        let content = self
+            .vcs
+            .read_git_stub_chain(
+                &git_stub,
+                &self.repo_root,
+                self.max_chain_depth,
+            )?
+            .into_contents();
 
-        let git_stub: GitStub = git_stub_contents.parse().map_err(|error| {
-            MaterializeError::InvalidGitStub { path: full_git_stub_path, error }
-        })?;
+        let written = write_output(output_path, &content)?;
+        self.record_state(output_path, git_stub_path, &git_stub, &content);
+        self.record_materialized(output_path, git_stub_path, &git_stub);
+        Ok(written)
+    }
+}
 
-        let content =
-            self.vcs.read_git_stub_contents(&git_stub, &self.repo_root)?;
+/// Runs `f` on Tokio's blocking pool, while materializing `git_stub_path`.
+///
+/// Panics in `f` are propagated. Returns an error if the task is cancelled
+/// because the runtime is shutting down.
+#[cfg(feature = "tokio")]
+async fn spawn_blocking<T: Send + 'static>(
+    git_stub_path: &Utf8Path,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, MaterializeError> {
+    tokio::task::spawn_blocking(f).await.map_err(|error| {
+        match error.try_into_panic() {
+            Ok(payload) => std::panic::resume_unwind(payload),
+            Err(_) => {
+                MaterializeError::Cancelled { path: git_stub_path.to_owned() }
+            }
+        }
+    })
+}
 
-        if let Some(parent) = output_path.parent() {
-            fs::create_dir_all(parent).map_err(|error| {
-                MaterializeError::CreateDir { path: parent.to_owned(), error }
+/// Loads the state file from `output_dir`.
+fn load_state(output_dir: &Utf8Path) -> Option<Arc<Mutex<MaterializeState>>> {
+    Some(Arc::new(Mutex::new(MaterializeState::load(output_dir))))
+}
+
+/// Locks `mutex`, ignoring poisoning: the state file is only a cache, and the
+/// sets of materialized and kept paths are only ever inserted into.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+/// Removes files within `dir` that aren't in `keep`, appending their paths
+/// to `removed`, and removes subdirectories left empty.
+///
+/// `is_root` is true for the output directory itself, where the state file
+/// is kept.
+///
+/// Returns whether `dir` is now empty.
+fn prune_dir(
+    dir: &Utf8Path,
+    is_root: bool,
+    keep: &BTreeSet<Utf8PathBuf>,
+    removed: &mut Vec<Utf8PathBuf>,
+) -> Result<bool, PruneError> {
+    let read_dir_error =
+        |error| PruneError::ReadDir { path: dir.to_owned(), error };
+    let mut is_empty = true;
+    for entry in dir.read_dir_utf8().map_err(read_dir_error)? {
+        let entry = entry.map_err(read_dir_error)?;
+        let path = entry.path();
+        // file_type doesn't follow symlinks, so symlinks to directories are
+        // removed rather than descended into.
+        let file_type = entry.file_type().map_err(read_dir_error)?;
+        if file_type.is_dir() {
+            if prune_dir(path, false, keep, removed)? {
+                std::fs::remove_dir(path).map_err(|error| {
+                    PruneError::RemoveDir { path: path.to_owned(), error }
+                })?;
+            } else {
+                is_empty = false;
+            }
+        } else if (is_root && entry.file_name() == STATE_FILE_NAME)
+            || keep.contains(&absolute_path(path))
+        {
+            is_empty = false;
+        } else {
+            std::fs::remove_file(path).map_err(|error| {
+                PruneError::RemoveFile { path: path.to_owned(), error }
             })?;
+            removed.push(path.to_owned());
         }
+    }
+    Ok(is_empty)
+}
 
-        AtomicFile::new(
-            output_path,
-            atomicwrites::OverwriteBehavior::AllowOverwrite,
-        )
-        .write(|f| f.write_all(&content))
-        .map_err(|error| {
-            use crate::errors::AtomicWriteError;
-            let error = match error {
-                atomicwrites::Error::Internal(e) => AtomicWriteError::Rename(e),
-                atomicwrites::Error::User(e) => AtomicWriteError::Write(e),
-            };
-            MaterializeError::WriteOutput {
-                path: output_path.to_owned(),
-                error,
+/// Escapes `path` for use in a depfile, as understood by both Make and Ninja.
+///
+/// Returns an error if `path` contains a newline, contains a colon (other
+/// than in a Windows prefix such as `C:`), or ends with a backslash: these
+/// can't be escaped in a way that both Make and Ninja understand.
+fn escape_depfile_path(path: &Utf8Path) -> Result<String, WriteDepfileError> {
+    let path_str = path.as_str();
+    let prefix_len = match path.components().next() {
+        Some(Utf8Component::Prefix(prefix)) => prefix.as_str().len(),
+        _ => 0,
+    };
+    if path_str.contains(['\n', '\r'])
+        || path_str[prefix_len..].contains(':')
+        || path_str.ends_with('\\')
+    {
+        return Err(WriteDepfileError::UnsupportedPath {
+            path: path.to_owned(),
+        });
+    }
+
+    let mut escaped = String::with_capacity(path_str.len());
+    // The number of backslashes immediately before the current character.
+    let mut backslashes = 0;
+    for c in path_str.chars() {
+        match c {
+            // Backslashes before an escaped character are doubled, so that
+            // they aren't read as part of the escape.
+            ' ' | '#' => {
+                escaped.extend(std::iter::repeat_n('\\', backslashes + 1));
+                escaped.push(c);
             }
+            '$' => escaped.push_str("$$"),
+            c => escaped.push(c),
+        }
+        backslashes = if c == '\\' { backslashes + 1 } else { 0 };
+    }
+    Ok(escaped)
+}
+
+/// Returns `path` made absolute, so that output paths can be compared
+/// regardless of how they were spelled.
+fn absolute_path(path: &Utf8Path) -> Utf8PathBuf {
+    std::path::absolute(path)
+        .ok()
+        .and_then(|path| Utf8PathBuf::from_path_buf(path).ok())
+        .unwrap_or_else(|| path.to_owned())
+}
+
+/// Returns an error if `git_stub_path` has a non-normal component or doesn't
+/// have a `.gitstub` extension.
+fn check_git_stub_path(
+    git_stub_path: &Utf8Path,
+) -> Result<(), MaterializeError> {
+    check_path(git_stub_path)?;
+
+    if git_stub_path.extension() != Some("gitstub") {
+        return Err(MaterializeError::NotGitStub {
+            path: git_stub_path.to_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// Returns a git stub for the git stub file at `git_stub_path` in `commit`.
+fn git_stub_location(
+    git_stub_path: &Utf8Path,
+    commit: GitCommitHash,
+) -> Result<GitStub, MaterializeError> {
+    GitStub::new(commit, git_stub_path.to_owned()).map_err(|error| {
+        MaterializeError::InvalidGitStub {
+            path: git_stub_path.to_owned(),
+            error,
+        }
+    })
+}
+
+fn read_at_commit_error(
+    git_stub_path: &Utf8Path,
+    commit: GitCommitHash,
+    error: ReadContentsError,
+) -> MaterializeError {
+    MaterializeError::ReadGitStubAtCommit {
+        path: git_stub_path.to_owned(),
+        commit,
+        error: Box::new(error),
+    }
+}
+
+/// Parses the contents of the git stub file at `full_git_stub_path`.
+fn parse_git_stub(
+    full_git_stub_path: &Utf8Path,
+    contents: &[u8],
+) -> Result<GitStub, MaterializeError> {
+    String::from_utf8_lossy(contents).parse().map_err(|error| {
+        MaterializeError::InvalidGitStub {
+            path: full_git_stub_path.to_owned(),
+            error,
+        }
+    })
+}
+
+/// Atomically writes `content` to `output_path`, creating parent directories
+/// as needed.
+///
+/// If `output_path` already has `content`, it is left untouched so that its
+/// modification time is preserved, and this returns false.
+fn write_output(
+    output_path: &Utf8Path,
+    content: &[u8],
+) -> Result<bool, MaterializeError> {
+    if has_contents(output_path, content) {
+        return Ok(false);
+    }
+
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent).map_err(|error| {
+            MaterializeError::CreateDir { path: parent.to_owned(), error }
         })?;
+    }
 
-        Ok(())
+    atomic_write(output_path, content).map_err(|error| {
+        MaterializeError::WriteOutput { path: output_path.to_owned(), error }
+    })?;
+    Ok(true)
+}
+
+/// Atomically writes `content` to `path`.
+pub(crate) fn atomic_write(
+    path: &Utf8Path,
+    content: &[u8],
+) -> Result<(), AtomicWriteError> {
+    AtomicFile::new(path, atomicwrites::OverwriteBehavior::AllowOverwrite)
+        .write(|f| f.write_all(content))
+        .map_err(|error| match error {
+            atomicwrites::Error::Internal(e) => AtomicWriteError::Rename(e),
+            atomicwrites::Error::User(e) => AtomicWriteError::Write(e),
+        })
+}
+
+/// Returns whether `path` is a file with exactly `content`.
+///
+/// Errors (e.g. if `path` doesn't exist) are treated as a mismatch, in which
+/// case the file is written and any real problem is reported then.
+fn has_contents(path: &Utf8Path, content: &[u8]) -> bool {
+    match std::fs::metadata(path) {
+        // Avoid reading the file if the size alone shows it differs.
+        Ok(meta) if meta.is_file() && meta.len() == content.len() as u64 => {
+            std::fs::read(path).is_ok_and(|existing| existing == content)
+        }
+        _ => false,
     }
 }
 
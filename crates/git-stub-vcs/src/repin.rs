@@ -0,0 +1,217 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Re-pinning git stubs whose commits are no longer reachable.
+
+use crate::{
+    CommitMapParseError, GitStubFile, RepinError, Vcs, scan::split_output,
+};
+use camino::Utf8Path;
+use git_stub::{GitCommitHash, GitStub};
+use std::{collections::BTreeMap, str::FromStr};
+
+/// A mapping from old to new commit hashes, as written by history rewriting
+/// tools.
+///
+/// # Format
+///
+/// This is the format of the `commit-map` file written by `git filter-repo`
+/// (in `.git/filter-repo/commit-map`): an optional `old new` header line,
+/// followed by one line per commit with the old and new hashes separated by
+/// whitespace. A new hash of all zeroes means the commit was removed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommitMap {
+    map: BTreeMap<GitCommitHash, Option<GitCommitHash>>,
+}
+
+impl CommitMap {
+    /// Returns the new commit hash for `old`.
+    ///
+    /// Returns `None` if `old` is not in the map, or if the commit was
+    /// removed.
+    pub fn get(&self, old: GitCommitHash) -> Option<GitCommitHash> {
+        self.map.get(&old).copied().flatten()
+    }
+}
+
+impl FromStr for CommitMap {
+    type Err = CommitMapParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut map = BTreeMap::new();
+        for (index, line) in s.lines().enumerate() {
+            let line_number = index + 1;
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let (old, new) = match fields[..] {
+                [] => continue,
+                ["old", "new"] if index == 0 => continue,
+                [old, new] => (old, new),
+                _ => {
+                    return Err(CommitMapParseError::InvalidLine {
+                        line_number,
+                        line: line.to_owned(),
+                    });
+                }
+            };
+            let parse = |hash: &str| {
+                hash.parse::<GitCommitHash>().map_err(|error| {
+                    CommitMapParseError::InvalidHash { line_number, error }
+                })
+            };
+            let old = parse(old)?;
+            let new = if new.bytes().all(|b| b == b'0') {
+                None
+            } else {
+                Some(parse(new)?)
+            };
+            map.insert(old, new);
+        }
+        Ok(CommitMap { map })
+    }
+}
+
+/// A git stub whose commit is not reachable from a protected revision, along
+/// with its replacement if one was found.
+///
+/// Returned by [`Vcs::repin_git_stubs`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Repin {
+    stub_file: GitStubFile,
+    new_stub: Option<GitStub>,
+}
+
+impl Repin {
+    /// Returns the git stub file, with its current contents.
+    pub fn stub_file(&self) -> &GitStubFile {
+        &self.stub_file
+    }
+
+    /// Returns the git stub to replace the current contents with, or `None`
+    /// if no equivalent commit was found.
+    pub fn new_stub(&self) -> Option<&GitStub> {
+        self.new_stub.as_ref()
+    }
+}
+
+impl Vcs {
+    /// Finds replacements for git stubs whose commits are not reachable from
+    /// `protected_revision`.
+    ///
+    /// This is the repair step for git stubs broken by squash merges or
+    /// history rewriting. For each git stub reported by
+    /// [`Vcs::find_unreachable_commits`]:
+    ///
+    /// 1. If `commit_map` maps the commit to a new commit that is reachable
+    ///    from `protected_revision` and contains the referenced path, the
+    ///    new commit is used.
+    /// 2. Otherwise, if the commit still exists, the oldest commit reachable
+    ///    from `protected_revision` in which the referenced path has an
+    ///    identical blob is used.
+    ///
+    /// Files are not modified: to re-pin a git stub, write
+    /// [`GitStub::to_file_contents`] for the new stub to the git stub file.
+    ///
+    /// Currently only supported for Git. In colocated jj repositories, use
+    /// [`Vcs::git`].
+    pub fn repin_git_stubs(
+        &self,
+        repo_root: &Utf8Path,
+        protected_revision: &str,
+        commit_map: Option<&CommitMap>,
+    ) -> Result<Vec<Repin>, RepinError> {
+        self.require_git("re-pinning git stubs")?;
+        let unreachable =
+            self.find_unreachable_commits(repo_root, protected_revision)?;
+
+        let mut repins = Vec::with_capacity(unreachable.len());
+        for unreachable in unreachable {
+            let stub_file = unreachable.stub_file().clone();
+            let stub = stub_file.stub();
+            let mapped = match commit_map.and_then(|m| m.get(stub.commit())) {
+                Some(commit) => self.mapped_stub(
+                    repo_root,
+                    stub,
+                    commit,
+                    protected_revision,
+                )?,
+                None => None,
+            };
+            let new_stub = match mapped {
+                Some(new_stub) => Some(new_stub),
+                None if unreachable.commit_exists() => self
+                    .find_equivalent_stub(
+                        repo_root,
+                        stub,
+                        protected_revision,
+                    )?,
+                None => None,
+            };
+            repins.push(Repin { stub_file, new_stub });
+        }
+        Ok(repins)
+    }
+
+    /// Returns `stub` pointed at `commit`, if `commit` is reachable from
+    /// `protected_revision` and contains the referenced path.
+    fn mapped_stub(
+        &self,
+        repo_root: &Utf8Path,
+        stub: &GitStub,
+        commit: GitCommitHash,
+        protected_revision: &str,
+    ) -> Result<Option<GitStub>, RepinError> {
+        let new_stub = with_commit(stub, commit);
+        if !self.commit_exists(repo_root, commit)?
+            || !self.is_ancestor(repo_root, commit, protected_revision)?
+        {
+            return Ok(None);
+        }
+        let blob_ids =
+            self.read_blob_ids(repo_root, std::slice::from_ref(&new_stub))?;
+        Ok(blob_ids[0].is_some().then_some(new_stub))
+    }
+
+    /// Finds the oldest commit reachable from `protected_revision` in which
+    /// the path referenced by `stub` has the same blob as in `stub`.
+    fn find_equivalent_stub(
+        &self,
+        repo_root: &Utf8Path,
+        stub: &GitStub,
+        protected_revision: &str,
+    ) -> Result<Option<GitStub>, RepinError> {
+        let blob_ids =
+            self.read_blob_ids(repo_root, std::slice::from_ref(stub))?;
+        let Some(blob_id) = &blob_ids[0] else {
+            return Ok(None);
+        };
+
+        // Only commits that touch the path can introduce the blob.
+        let pathspec = format!(":(literal){}", stub.path());
+        let args =
+            ["rev-list", "--reverse", protected_revision, "--", &pathspec];
+        let stdout = self.run(repo_root, &args, None)?;
+        let mut candidates = Vec::new();
+        for line in split_output(&stdout, b'\n') {
+            let commit = line.parse().map_err(|_| {
+                self.unexpected_output(
+                    &args,
+                    format!("invalid commit hash {line:?}"),
+                )
+            })?;
+            candidates.push(with_commit(stub, commit));
+        }
+
+        let candidate_blob_ids = self.read_blob_ids(repo_root, &candidates)?;
+        let new_stub = candidates
+            .into_iter()
+            .zip(candidate_blob_ids)
+            .find(|(_, id)| id.as_ref() == Some(blob_id))
+            .map(|(candidate, _)| candidate);
+        Ok(new_stub)
+    }
+}
+
+/// Returns a copy of `stub` that points at `commit`.
+fn with_commit(stub: &GitStub, commit: GitCommitHash) -> GitStub {
+    GitStub::new(commit, stub.path().to_owned())
+        .expect("path was already validated")
+}
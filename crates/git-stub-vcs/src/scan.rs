@@ -0,0 +1,219 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Finding git stubs in a repository.
+
+use crate::{FindGitStubsError, Vcs, VcsName};
+use camino::{Utf8Path, Utf8PathBuf};
+use fs_err as fs;
+use git_stub::GitStub;
+use std::io;
+
+/// A git stub file found in a repository.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GitStubFile {
+    path: Utf8PathBuf,
+    stub: GitStub,
+}
+
+impl GitStubFile {
+    /// Returns the path to the git stub file, relative to the repository
+    /// root.
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+
+    /// Returns the parsed git stub.
+    pub fn stub(&self) -> &GitStub {
+        &self.stub
+    }
+}
+
+impl Vcs {
+    /// Finds the git stub files (files with a `.gitstub` extension) in the
+    /// working tree at `repo_root`, sorted by path.
+    ///
+    /// `repo_root` must be the repository root.
+    ///
+    /// For Git, runs `git ls-files` to list tracked files, as well as
+    /// untracked files that aren't ignored. For Jujutsu, runs
+    /// `jj file list`, which snapshots the working copy first.
+    ///
+    /// Returns an error if any git stub file can't be parsed.
+    pub fn find_git_stubs(
+        &self,
+        repo_root: &Utf8Path,
+    ) -> Result<Vec<GitStubFile>, FindGitStubsError> {
+        let mut paths = match self.name() {
+            VcsName::Git => {
+                let stdout = self.run(
+                    repo_root,
+                    &[
+                        "ls-files",
+                        "-z",
+                        "--cached",
+                        "--others",
+                        "--exclude-standard",
+                        "--",
+                        "*.gitstub",
+                    ],
+                    None,
+                )?;
+                split_output(&stdout, b'\0')
+            }
+            VcsName::Jj => {
+                let stdout = self.run(
+                    repo_root,
+                    &[
+                        "file",
+                        "list",
+                        "--revision",
+                        "@",
+                        "--",
+                        "glob:**/*.gitstub",
+                    ],
+                    None,
+                )?;
+                split_output(&stdout, b'\n')
+            }
+        }
+        .into_iter()
+        .map(Utf8PathBuf::from)
+        .collect::<Vec<_>>();
+        // Git lists a conflicted path once per stage.
+        paths.sort();
+        paths.dedup();
+
+        let mut stub_files = Vec::with_capacity(paths.len());
+        for path in paths {
+            if path.extension() != Some("gitstub") {
+                continue;
+            }
+            let contents = match fs::read_to_string(repo_root.join(&path)) {
+                Ok(contents) => contents,
+                // Tracked files that were deleted from the working tree.
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    continue;
+                }
+                Err(error) => {
+                    return Err(FindGitStubsError::ReadGitStub { path, error });
+                }
+            };
+            let stub = contents.parse().map_err(|error| {
+                FindGitStubsError::InvalidGitStub { path: path.clone(), error }
+            })?;
+            stub_files.push(GitStubFile { path, stub });
+        }
+        Ok(stub_files)
+    }
+
+    /// Finds the git stub files (files with a `.gitstub` extension) at
+    /// `revision`, sorted by path.
+    ///
+    /// `repo_root` must be the repository root, and `revision` uses the
+    /// syntax of the VCS.
+    ///
+    /// For Git, runs `git ls-tree` and `git cat-file --batch`. For Jujutsu,
+    /// runs `jj file list` and `jj file show` for each git stub.
+    ///
+    /// Returns an error if any git stub file can't be parsed.
+    pub fn find_git_stubs_at(
+        &self,
+        repo_root: &Utf8Path,
+        revision: &str,
+    ) -> Result<Vec<GitStubFile>, FindGitStubsError> {
+        let mut files = match self.name() {
+            VcsName::Git => {
+                let args = ["ls-tree", "-r", "-z", "--full-tree", revision];
+                let stdout = self.run(repo_root, &args, None)?;
+                let mut paths = Vec::new();
+                let mut objects = Vec::new();
+                for entry in split_output(&stdout, b'\0') {
+                    // Each entry is `<mode> <type> <object ID>\t<path>`.
+                    let Some((info, path)) = entry.split_once('\t') else {
+                        return Err(self
+                            .unexpected_output(
+                                &args,
+                                format!("invalid entry {entry:?}"),
+                            )
+                            .into());
+                    };
+                    let path = Utf8PathBuf::from(path);
+                    if path.extension() != Some("gitstub") {
+                        continue;
+                    }
+                    if let [_, "blob", object_id] =
+                        info.split(' ').collect::<Vec<_>>()[..]
+                    {
+                        paths.push(path);
+                        objects.push(object_id.to_owned());
+                    }
+                }
+                let blobs = self.read_blobs(repo_root, &objects)?;
+                paths
+                    .into_iter()
+                    .zip(blobs)
+                    .filter_map(|(path, blob)| Some((path, blob?)))
+                    .collect()
+            }
+            VcsName::Jj => {
+                let stdout = self.run(
+                    repo_root,
+                    &[
+                        "file",
+                        "list",
+                        "--ignore-working-copy",
+                        "--revision",
+                        revision,
+                        "--",
+                        "glob:**/*.gitstub",
+                    ],
+                    None,
+                )?;
+                let mut files = Vec::new();
+                for path in split_output(&stdout, b'\n') {
+                    // Match the path exactly, rather than as a fileset
+                    // expression.
+                    let fileset = format!("root-file:{:?}", path);
+                    let contents = self.run(
+                        repo_root,
+                        &[
+                            "file",
+                            "show",
+                            "--ignore-working-copy",
+                            "--revision",
+                            revision,
+                            "--",
+                            &fileset,
+                        ],
+                        None,
+                    )?;
+                    files.push((Utf8PathBuf::from(path), contents));
+                }
+                files
+            }
+        };
+        files.sort();
+
+        files
+            .into_iter()
+            .map(|(path, contents)| {
+                let stub = String::from_utf8_lossy(&contents).parse().map_err(
+                    |error| FindGitStubsError::InvalidGitStub {
+                        path: path.clone(),
+                        error,
+                    },
+                )?;
+                Ok(GitStubFile { path, stub })
+            })
+            .collect()
+    }
+}
+
+/// Splits VCS output into entries, skipping empty ones.
+pub(crate) fn split_output(stdout: &[u8], separator: u8) -> Vec<String> {
+    stdout
+        .split(|&b| b == separator)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| String::from_utf8_lossy(entry).into_owned())
+        .collect()
+}
@@ -0,0 +1,249 @@
+// Copyright 2026 Oxide Computer Company
+
+//! The incremental materialization state file.
+
+use atomicwrites::{AtomicFile, OverwriteBehavior};
+use camino::{Utf8Path, Utf8PathBuf};
+use git_stub::GitStub;
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, io::Write};
+
+/// The name of the state file within the output directory.
+pub(crate) const STATE_FILE_NAME: &str = ".git-stub-state";
+
+/// The first line of the state file. Files with any other first line are
+/// ignored, so this must be changed whenever the format changes.
+const HEADER: &str = "# git-stub-vcs state v1";
+
+/// What was last materialized to each output path.
+///
+/// # Format
+///
+/// After the header, one line per output path, with four tab-separated
+/// fields: the output path (relative to the output directory if it is within
+/// it), the git stub path, the git stub, and the SHA-256 digest of the
+/// contents that were written. Entries with a tab or newline in any field
+/// aren't recorded.
+///
+/// The state file is a cache: if it is missing or can't be parsed, it is
+/// treated as empty.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MaterializeState {
+    entries: BTreeMap<String, StateEntry>,
+    dirty: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct StateEntry {
+    git_stub_path: String,
+    stub: String,
+    digest: String,
+}
+
+impl MaterializeState {
+    /// Loads the state file from `output_dir`, or returns an empty state if
+    /// it is missing or invalid.
+    pub(crate) fn load(output_dir: &Utf8Path) -> Self {
+        std::fs::read_to_string(output_dir.join(STATE_FILE_NAME))
+            .ok()
+            .and_then(|contents| Self::parse(&contents))
+            .unwrap_or_default()
+    }
+
+    fn parse(contents: &str) -> Option<Self> {
+        let mut lines = contents.lines();
+        if lines.next() != Some(HEADER) {
+            return None;
+        }
+        let mut entries = BTreeMap::new();
+        for line in lines {
+            let fields: Vec<_> = line.split('\t').collect();
+            let [output_path, git_stub_path, stub, digest] = fields[..] else {
+                return None;
+            };
+            entries.insert(
+                output_path.to_owned(),
+                StateEntry {
+                    git_stub_path: git_stub_path.to_owned(),
+                    stub: stub.to_owned(),
+                    digest: digest.to_owned(),
+                },
+            );
+        }
+        Some(MaterializeState { entries, dirty: false })
+    }
+
+    /// Writes the state file to `output_dir` if it has changed.
+    ///
+    /// Errors are ignored, since the state file is only a cache.
+    pub(crate) fn save(&mut self, output_dir: &Utf8Path) {
+        if !self.dirty {
+            return;
+        }
+        let contents = self.to_file_contents();
+        if std::fs::create_dir_all(output_dir).is_err() {
+            return;
+        }
+        let file = AtomicFile::new(
+            output_dir.join(STATE_FILE_NAME),
+            OverwriteBehavior::AllowOverwrite,
+        );
+        if file.write(|f| f.write_all(contents.as_bytes())).is_ok() {
+            self.dirty = false;
+        }
+    }
+
+    fn to_file_contents(&self) -> String {
+        let mut contents = format!("{HEADER}\n");
+        for (output_path, entry) in &self.entries {
+            contents.push_str(&format!(
+                "{output_path}\t{}\t{}\t{}\n",
+                entry.git_stub_path, entry.stub, entry.digest
+            ));
+        }
+        contents
+    }
+
+    /// Returns whether `stub` (read from `git_stub_path`) was last
+    /// materialized to `output_path`, and the output file still has the
+    /// contents that were written.
+    pub(crate) fn is_fresh(
+        &self,
+        output_dir: &Utf8Path,
+        output_path: &Utf8Path,
+        git_stub_path: &Utf8Path,
+        stub: &GitStub,
+    ) -> bool {
+        let key = state_key(output_dir, output_path);
+        let Some(entry) = self.entries.get(key.as_str()) else {
+            return false;
+        };
+        entry.git_stub_path == git_stub_path.as_str()
+            && entry.stub == stub.to_string()
+            && file_digest(output_path).is_some_and(|d| d == entry.digest)
+    }
+
+    /// Removes the entry for `output_path`, if any.
+    pub(crate) fn forget(
+        &mut self,
+        output_dir: &Utf8Path,
+        output_path: &Utf8Path,
+    ) {
+        let key = state_key(output_dir, output_path);
+        if self.entries.remove(&key).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    /// Records that `stub` (read from `git_stub_path`) was materialized to
+    /// `output_path` with `contents`.
+    pub(crate) fn record(
+        &mut self,
+        output_dir: &Utf8Path,
+        output_path: &Utf8Path,
+        git_stub_path: &Utf8Path,
+        stub: &GitStub,
+        contents: &[u8],
+    ) {
+        let key = state_key(output_dir, output_path);
+        let entry = StateEntry {
+            git_stub_path: git_stub_path.to_string(),
+            stub: stub.to_string(),
+            digest: digest(contents),
+        };
+        let fields = [&key, &entry.git_stub_path, &entry.stub];
+        if fields.iter().any(|field| field.contains(['\t', '\n', '\r'])) {
+            return;
+        }
+        if self.entries.get(&key) != Some(&entry) {
+            self.entries.insert(key, entry);
+            self.dirty = true;
+        }
+    }
+}
+
+/// Returns the key for `output_path`: relative to `output_dir` if it is
+/// within it, and unchanged otherwise.
+fn state_key(output_dir: &Utf8Path, output_path: &Utf8Path) -> String {
+    output_path
+        .strip_prefix(output_dir)
+        .map(Utf8PathBuf::from)
+        .unwrap_or_else(|_| output_path.to_owned())
+        .into_string()
+}
+
+/// Returns the hex-encoded SHA-256 digest of `contents`.
+fn digest(contents: &[u8]) -> String {
+    hex::encode(Sha256::digest(contents))
+}
+
+/// Returns the hex-encoded SHA-256 digest of the file at `path`, or `None`
+/// if it can't be read.
+fn file_digest(path: &Utf8Path) -> Option<String> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).ok()?;
+    Some(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_roundtrip() {
+        let stub: GitStub =
+            format!("{}:openapi/api.json", "ab".repeat(20)).parse().unwrap();
+        let output_dir = Utf8Path::new("out");
+        let output_path = output_dir.join("api.json");
+        let git_stub_path = Utf8Path::new("api.json.gitstub");
+
+        let mut state = MaterializeState::default();
+        state.record(output_dir, &output_path, git_stub_path, &stub, b"{}");
+        assert!(state.dirty, "recording a new entry marks the state dirty");
+
+        let parsed =
+            MaterializeState::parse(&state.to_file_contents()).unwrap();
+        assert_eq!(parsed.entries, state.entries);
+        assert_eq!(
+            parsed.entries["api.json"].digest,
+            "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a",
+            "digest is the SHA-256 of the contents"
+        );
+
+        let temp = camino_tempfile::Utf8TempDir::new().unwrap();
+        let output_dir = temp.path();
+        let output_path = output_dir.join("api.json");
+        state.record(output_dir, &output_path, git_stub_path, &stub, b"{}");
+        assert!(
+            !state.is_fresh(output_dir, &output_path, git_stub_path, &stub),
+            "missing output isn't fresh"
+        );
+        std::fs::write(&output_path, "{}").unwrap();
+        assert!(
+            state.is_fresh(output_dir, &output_path, git_stub_path, &stub),
+            "output with the recorded contents is fresh"
+        );
+        assert!(
+            !state.is_fresh(
+                output_dir,
+                &output_path,
+                Utf8Path::new("other.gitstub"),
+                &stub,
+            ),
+            "output from a different git stub path isn't fresh"
+        );
+        std::fs::write(&output_path, "[]").unwrap();
+        assert!(
+            !state.is_fresh(output_dir, &output_path, git_stub_path, &stub),
+            "modified output isn't fresh"
+        );
+
+        assert!(MaterializeState::parse("").is_none(), "missing header");
+        assert!(
+            MaterializeState::parse(&format!("{HEADER}\nonly\ttwo\n"))
+                .is_none(),
+            "wrong number of fields"
+        );
+    }
+}
@@ -0,0 +1,39 @@
+// Copyright 2026 Oxide Computer Company
+
+//! A Git `textconv` driver for reviewing changes to git stubs.
+//!
+//! By default, `git diff` on a `.gitstub` file shows two `commit:path` lines,
+//! which says nothing about what actually changed. With this driver, Git
+//! diffs the referenced contents instead:
+//!
+//! ```text
+//! git config diff.gitstub.textconv "git-stub textconv"
+//! git config diff.gitstub.cachetextconv true
+//! echo '*.gitstub diff=gitstub' >> .gitattributes
+//! ```
+//!
+//! To see the git stubs themselves, pass `--no-textconv` to `git diff`.
+
+use crate::{ReadContentsError, Vcs, filter::parse_stub};
+use camino::Utf8Path;
+
+/// Converts the contents of a git stub file into the contents of the file
+/// it references, for use as a Git `textconv` driver.
+///
+/// If `contents` is not a valid git stub (for example, because it has merge
+/// conflict markers), it is returned unchanged.
+///
+/// Git runs diff drivers with its own environment, which may select the
+/// repository (for example, `GIT_DIR` with `git --git-dir`). To respect it,
+/// pass a `vcs` with [environment isolation](Vcs#environment-isolation)
+/// disabled.
+pub fn textconv(
+    vcs: &Vcs,
+    repo_root: &Utf8Path,
+    contents: &[u8],
+) -> Result<Vec<u8>, ReadContentsError> {
+    match parse_stub(contents) {
+        Some(stub) => vcs.read_git_stub_contents(&stub, repo_root),
+        None => Ok(contents.to_vec()),
+    }
+}
@@ -3,12 +3,19 @@
 //! Version control system abstraction for reading file contents from history.
 
 use crate::{
-    ReadContentsError, ShallowCloneError, VcsDetectError, VcsEnvError,
+    CancellationToken, ReadContentsError, ReadIndexError, ShallowCloneError,
+    VcsCommandError, VcsDetectError, VcsEnvError, scan::split_output,
 };
-use camino::Utf8Path;
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
 use fs_err as fs;
-use git_stub::GitStub;
-use std::{fmt, io, process::Command};
+use git_stub::{GitCommitHash, GitStub};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    process::{Command, Output, Stdio},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
 /// Reads a VCS binary path from an environment variable, falling back
 /// to `default` if the variable is unset or empty.
@@ -36,6 +43,158 @@ fn read_vcs_env(
     }
 }
 
+/// How often to check whether a VCS process has exited, when a timeout or
+/// cancellation token is set.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// An error from waiting for a VCS process, converted by callers into their
+/// own error types.
+#[derive(Debug)]
+enum WaitError {
+    /// Failed to spawn the process or collect its output.
+    Io(io::Error),
+    /// The process didn't exit within the timeout, and was killed.
+    TimedOut(Duration),
+    /// The cancellation token was triggered, and the process was killed.
+    Cancelled,
+}
+
+/// Reads `reader` to the end on a separate thread.
+fn read_to_end(
+    mut reader: impl Read + Send + 'static,
+) -> JoinHandle<io::Result<Vec<u8>>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        Ok(buf)
+    })
+}
+
+/// Environment variables that point Git at a particular repository, as
+/// listed by `git rev-parse --local-env-vars`.
+///
+/// These are cleared for VCS commands unless environment isolation is
+/// disabled.
+const GIT_REPO_ENV_VARS: &[&str] = &[
+    "GIT_ALTERNATE_OBJECT_DIRECTORIES",
+    "GIT_CONFIG",
+    "GIT_CONFIG_PARAMETERS",
+    "GIT_CONFIG_COUNT",
+    "GIT_OBJECT_DIRECTORY",
+    "GIT_DIR",
+    "GIT_WORK_TREE",
+    "GIT_IMPLICIT_WORK_TREE",
+    "GIT_GRAFT_FILE",
+    "GIT_INDEX_FILE",
+    "GIT_NO_REPLACE_OBJECTS",
+    "GIT_REPLACE_REF_BASE",
+    "GIT_PREFIX",
+    "GIT_INTERNAL_SUPER_PREFIX",
+    "GIT_SHALLOW_FILE",
+    "GIT_COMMON_DIR",
+];
+
+/// Returns an error if `repo_root` is not an existing directory.
+fn check_dir(repo_root: &Utf8Path) -> Result<(), VcsDetectError> {
+    // Use metadata() to distinguish "not a directory" from I/O
+    // errors (e.g., permission denied).
+    match fs::metadata(repo_root) {
+        Ok(meta) if meta.is_dir() => Ok(()),
+        Ok(_) => Err(VcsDetectError::NotADirectory {
+            repo_root: repo_root.to_owned(),
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            Err(VcsDetectError::PathNotFound {
+                repo_root: repo_root.to_owned(),
+            })
+        }
+        Err(err) => {
+            Err(VcsDetectError::Io { path: repo_root.to_owned(), source: err })
+        }
+    }
+}
+
+/// Checks that if `.jj/repo` is a file (as in secondary jj workspaces), the
+/// repository it points to exists.
+fn check_jj_repo_file(jj_path: &Utf8Path) -> Result<(), VcsDetectError> {
+    let repo_path = jj_path.join("repo");
+    let Some(contents) = read_if_file(&repo_path)? else {
+        return Ok(());
+    };
+    // The path is relative to the `.jj` directory.
+    let repo_dir = jj_path.join(contents.trim());
+    match repo_dir.try_exists() {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            Err(VcsDetectError::DanglingJjRepo { path: repo_path, repo_dir })
+        }
+        Err(source) => Err(VcsDetectError::Io { path: repo_dir, source }),
+    }
+}
+
+/// Checks that if `.git` is a file (as in Git worktrees and submodules), the
+/// Git directory it points to exists.
+fn check_git_file(git_path: &Utf8Path) -> Result<(), VcsDetectError> {
+    let Some(contents) = read_if_file(git_path)? else {
+        return Ok(());
+    };
+    let Some(git_dir) = contents.trim().strip_prefix("gitdir:") else {
+        return Err(VcsDetectError::InvalidGitFile {
+            path: git_path.to_owned(),
+        });
+    };
+    // The path is relative to the directory containing the `.git` file.
+    let parent = git_path.parent().expect(".git path has a parent");
+    let git_dir = parent.join(git_dir.trim());
+    match git_dir.try_exists() {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(VcsDetectError::DanglingGitDir {
+            path: git_path.to_owned(),
+            git_dir,
+        }),
+        Err(source) => Err(VcsDetectError::Io { path: git_dir, source }),
+    }
+}
+
+/// Returns the contents of `path` if it is a file, or `None` if it is a
+/// directory or doesn't exist.
+fn read_if_file(path: &Utf8Path) -> Result<Option<String>, VcsDetectError> {
+    let io_error =
+        |source| VcsDetectError::Io { path: path.to_owned(), source };
+    match fs::metadata(path) {
+        Ok(meta) if meta.is_file() => {
+            fs::read_to_string(path).map(Some).map_err(io_error)
+        }
+        Ok(_) => Ok(None),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(io_error(err)),
+    }
+}
+
+/// Returns whether `dir` looks like a Git directory, in the same way as Git
+/// itself: it has a `HEAD` file and `objects` and `refs` directories.
+fn is_git_dir(dir: &Utf8Path) -> bool {
+    dir.join("HEAD").is_file()
+        && dir.join("objects").is_dir()
+        && dir.join("refs").is_dir()
+}
+
+/// Removes `.` and `..` components from an absolute path without touching
+/// the filesystem (in the same way as Cargo).
+fn normalize_lexically(path: &Utf8Path) -> Utf8PathBuf {
+    let mut normalized = Utf8PathBuf::new();
+    for component in path.components() {
+        match component {
+            Utf8Component::CurDir => {}
+            Utf8Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
 /// The name of a version control system.
 ///
 /// Used in error messages and for identifying which VCS is in use.
@@ -61,8 +220,30 @@ impl fmt::Display for VcsName {
 ///
 /// Supports Git and Jujutsu (jj). Use [`Vcs::git()`], [`Vcs::jj()`], or
 /// [`Vcs::detect()`].
+///
+/// # Environment isolation
+///
+/// By default, VCS commands are run with a sanitized environment, so that
+/// they operate on the repository they are run in and produce parseable
+/// output:
+///
+/// * Environment variables that point Git at a particular repository (as
+///   listed by `git rev-parse --local-env-vars`, e.g. `GIT_DIR`,
+///   `GIT_WORK_TREE` and `GIT_INDEX_FILE`) are cleared. Git sets these when
+///   running hooks, so without this, running Cargo from a hook would read
+///   the hook's repository rather than `repo_root`.
+/// * The pager, color output and (for Git) `core.fsmonitor` hooks are
+///   disabled, and Git never prompts for credentials.
+/// * `LC_ALL` is set to `C`.
+///
+/// Use [`Vcs::with_env_isolation`] to opt out.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Vcs(VcsKind);
+pub struct Vcs {
+    kind: VcsKind,
+    isolate_env: bool,
+    timeout: Option<Duration>,
+    cancellation: Option<CancellationToken>,
+}
 
 /// The internal representation of a VCS.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -80,6 +261,11 @@ enum VcsKind {
 }
 
 impl Vcs {
+    /// Creates a VCS with the default settings.
+    fn new(kind: VcsKind) -> Self {
+        Vcs { kind, isolate_env: true, timeout: None, cancellation: None }
+    }
+
     /// Creates a Git VCS using the `$GIT` environment variable or
     /// `"git"`.
     ///
@@ -87,7 +273,7 @@ impl Vcs {
     /// but is not valid UTF-8.
     pub fn git() -> Result<Self, VcsEnvError> {
         let binary = read_vcs_env("GIT", "git")?;
-        Ok(Vcs(VcsKind::Git { binary }))
+        Ok(Vcs::new(VcsKind::Git { binary }))
     }
 
     /// Creates a Jujutsu VCS using the `$JJ` environment variable
@@ -97,7 +283,7 @@ impl Vcs {
     /// but is not valid UTF-8.
     pub fn jj() -> Result<Self, VcsEnvError> {
         let binary = read_vcs_env("JJ", "jj")?;
-        Ok(Vcs(VcsKind::Jj { binary }))
+        Ok(Vcs::new(VcsKind::Jj { binary }))
     }
 
     /// Detects the appropriate VCS for a repository.
@@ -109,66 +295,187 @@ impl Vcs {
     ///    mode where both `.jj` and `.git` exist).
     /// 2. If a `.git` path exists, returns git. (`.git` may be a
     ///    directory or a file, as in worktrees and submodules.)
-    /// 3. Otherwise, returns an error.
+    /// 3. If `repo_root` is itself a Git directory (a bare repository, or
+    ///    the `.git` directory of a working tree), returns git.
+    /// 4. Otherwise, returns an error.
+    ///
+    /// In secondary jj workspaces, `.jj/repo` is a file containing the path
+    /// to the main repository's store; in Git worktrees and submodules,
+    /// `.git` is a file containing `gitdir: <path>`. Returns an error if the
+    /// path is missing, for example because the main repository was moved
+    /// or the worktree was pruned.
     pub fn detect(repo_root: &Utf8Path) -> Result<Self, VcsDetectError> {
-        // Use metadata() to distinguish "not a directory" from I/O
-        // errors (e.g., permission denied).
-        match fs::metadata(repo_root) {
-            Ok(meta) if meta.is_dir() => {}
-            Ok(_) => {
-                return Err(VcsDetectError::NotADirectory {
-                    repo_root: repo_root.to_owned(),
-                });
-            }
-            Err(err) if err.kind() == io::ErrorKind::NotFound => {
-                return Err(VcsDetectError::PathNotFound {
-                    repo_root: repo_root.to_owned(),
-                });
-            }
-            Err(err) => {
-                return Err(VcsDetectError::Io {
-                    path: repo_root.to_owned(),
-                    source: err,
-                });
+        check_dir(repo_root)?;
+        if let Some(vcs) = Self::detect_at(repo_root)? {
+            return Ok(vcs);
+        }
+        // A Git directory itself: a bare repository, or the `.git` directory
+        // of a working tree. This is only accepted when explicitly given, not
+        // during discovery, so that discovering from inside `.git` finds the
+        // working tree.
+        if is_git_dir(repo_root) {
+            return Ok(Self::git()?);
+        }
+        Err(VcsDetectError::NotFound { repo_root: repo_root.to_owned() })
+    }
+
+    /// Discovers the repository containing `start`, by walking up from
+    /// `start` to the nearest directory with a `.jj` or `.git`.
+    ///
+    /// Returns the VCS (detected as in [`Vcs::detect`]) and the repository
+    /// root. If `start` is relative, it is resolved against the current
+    /// working directory, and the returned root is absolute.
+    ///
+    /// Unlike [`Vcs::detect`], Git directories themselves are not accepted:
+    /// discovering from inside a `.git` directory returns the working tree
+    /// that contains it, and bare repositories are not found.
+    ///
+    /// Use this instead of [`Vcs::detect`] when the exact location of the
+    /// repository root is not known, e.g. in a build script for a crate
+    /// that may be moved within a workspace.
+    pub fn discover(
+        start: &Utf8Path,
+    ) -> Result<(Self, Utf8PathBuf), VcsDetectError> {
+        check_dir(start)?;
+        let absolute = std::path::absolute(start)
+            .map_err(|source| VcsDetectError::Io {
+                path: start.to_owned(),
+                source,
+            })
+            .and_then(|path| {
+                Utf8PathBuf::from_path_buf(path).map_err(|path| {
+                    VcsDetectError::Io {
+                        path: start.to_owned(),
+                        source: io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{} is not valid UTF-8", path.display()),
+                        ),
+                    }
+                })
+            })?;
+
+        for dir in normalize_lexically(&absolute).ancestors() {
+            if let Some(vcs) = Self::detect_at(dir)? {
+                return Ok((vcs, dir.to_owned()));
             }
         }
+        Err(VcsDetectError::NotFoundInAncestors { start: start.to_owned() })
+    }
 
-        let jj_path = repo_root.join(".jj");
+    /// Returns the VCS for `dir` (which must be a directory), or `None` if
+    /// it has neither `.jj` nor `.git`.
+    fn detect_at(dir: &Utf8Path) -> Result<Option<Self>, VcsDetectError> {
+        let jj_path = dir.join(".jj");
         match jj_path.try_exists() {
-            Ok(true) => return Ok(Self::jj()?),
+            Ok(true) => {
+                check_jj_repo_file(&jj_path)?;
+                return Ok(Some(Self::jj()?));
+            }
             Ok(false) => {}
             Err(source) => {
                 return Err(VcsDetectError::Io { path: jj_path, source });
             }
         }
 
-        let git_path = repo_root.join(".git");
+        let git_path = dir.join(".git");
         match git_path.try_exists() {
-            Ok(true) => return Ok(Self::git()?),
+            Ok(true) => {
+                check_git_file(&git_path)?;
+                return Ok(Some(Self::git()?));
+            }
             Ok(false) => {}
             Err(source) => {
                 return Err(VcsDetectError::Io { path: git_path, source });
             }
         }
 
-        Err(VcsDetectError::NotFound { repo_root: repo_root.to_owned() })
+        Ok(None)
     }
 
     /// Returns the path to the VCS binary.
     pub fn binary(&self) -> &str {
-        match &self.0 {
+        match &self.kind {
             VcsKind::Git { binary } | VcsKind::Jj { binary } => binary,
         }
     }
 
     /// Returns the name of the VCS.
     pub fn name(&self) -> VcsName {
-        match &self.0 {
+        match &self.kind {
             VcsKind::Git { .. } => VcsName::Git,
             VcsKind::Jj { .. } => VcsName::Jj,
         }
     }
 
+    /// Sets whether VCS commands are run with a sanitized environment.
+    ///
+    /// Isolation is enabled by default; see [the type-level
+    /// documentation](Self#environment-isolation) for details. Disable it
+    /// if the inherited environment is meant to select the repository, e.g.
+    /// when running as a Git filter or diff driver.
+    pub fn with_env_isolation(mut self, isolate_env: bool) -> Self {
+        self.isolate_env = isolate_env;
+        self
+    }
+
+    /// Returns whether VCS commands are run with a sanitized environment.
+    pub fn env_isolation(&self) -> bool {
+        self.isolate_env
+    }
+
+    /// Sets the maximum time each VCS command may run for.
+    ///
+    /// A command that doesn't finish in time (for example, because it is
+    /// waiting on a lock, a credential prompt or a network fetch) is killed,
+    /// and the operation returns a `TimedOut` error (e.g.
+    /// [`ReadContentsError::TimedOut`]). By default, there is no timeout.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the maximum time each VCS command may run for, if set.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Sets a token that cancels VCS commands.
+    ///
+    /// Once `token` is cancelled, running commands are killed, and
+    /// operations return a `Cancelled` error (e.g.
+    /// [`ReadContentsError::Cancelled`]) rather than starting new commands.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Returns a `Command` for the VCS binary that runs in `repo_root`,
+    /// with the environment sanitized unless isolation is disabled.
+    fn command(&self, repo_root: &Utf8Path) -> Command {
+        let mut cmd = Command::new(self.binary());
+        cmd.current_dir(repo_root);
+        if !self.isolate_env {
+            return cmd;
+        }
+
+        for var in GIT_REPO_ENV_VARS {
+            cmd.env_remove(var);
+        }
+        cmd.env("LC_ALL", "C");
+        match &self.kind {
+            VcsKind::Git { .. } => {
+                cmd.env("GIT_PAGER", "cat")
+                    .env("GIT_TERMINAL_PROMPT", "0")
+                    .args(["-c", "color.ui=false"])
+                    .args(["-c", "core.fsmonitor=false"]);
+            }
+            VcsKind::Jj { .. } => {
+                cmd.args(["--no-pager", "--color", "never"]);
+            }
+        }
+        cmd
+    }
+
     /// Checks if the repository at `repo_root` is a shallow clone.
     ///
     /// For Git, runs `git rev-parse --is-shallow-repository`.
@@ -179,17 +486,16 @@ impl Vcs {
         &self,
         repo_root: &Utf8Path,
     ) -> Result<bool, ShallowCloneError> {
-        match &self.0 {
-            VcsKind::Git { binary } => {
-                let output = Command::new(binary)
-                    .current_dir(repo_root)
-                    .args(["rev-parse", "--is-shallow-repository"])
-                    .output()
-                    .map_err(|source| ShallowCloneError::SpawnFailed {
-                        vcs_name: VcsName::Git,
-                        binary_path: binary.clone(),
-                        repo_root: repo_root.to_owned(),
-                        source,
+        match &self.kind {
+            VcsKind::Git { .. } => {
+                let output = self
+                    .output(
+                        self.command(repo_root)
+                            .args(["rev-parse", "--is-shallow-repository"]),
+                        None,
+                    )
+                    .map_err(|error| {
+                        self.shallow_clone_error(repo_root, error)
                     })?;
 
                 if output.status.success() {
@@ -211,16 +517,18 @@ impl Vcs {
                     })
                 }
             }
-            VcsKind::Jj { binary } => {
-                let output = Command::new(binary)
-                    .current_dir(repo_root)
-                    .args(["git", "root", "--ignore-working-copy"])
-                    .output()
-                    .map_err(|source| ShallowCloneError::SpawnFailed {
-                        vcs_name: VcsName::Jj,
-                        binary_path: binary.clone(),
-                        repo_root: repo_root.to_owned(),
-                        source,
+            VcsKind::Jj { .. } => {
+                let output = self
+                    .output(
+                        self.command(repo_root).args([
+                            "git",
+                            "root",
+                            "--ignore-working-copy",
+                        ]),
+                        None,
+                    )
+                    .map_err(|error| {
+                        self.shallow_clone_error(repo_root, error)
                     })?;
 
                 if !output.status.success() {
@@ -259,13 +567,37 @@ impl Vcs {
         stub: &GitStub,
         repo_root: &Utf8Path,
     ) -> Result<Vec<u8>, ReadContentsError> {
-        let vcs_name = self.name();
-        let binary_path = self.binary().to_string();
+        let mut cmd = self.read_contents_command(stub, repo_root);
+        let output = self.output(&mut cmd, None);
+        self.read_contents_result(stub, repo_root, output)
+    }
 
-        let mut cmd = Command::new(self.binary());
-        cmd.current_dir(repo_root);
+    /// Asynchronously reads the contents of the file referenced by a git
+    /// stub.
+    ///
+    /// This is the asynchronous version of [`Vcs::read_git_stub_contents`].
+    /// It must be called from within a Tokio runtime, with the time driver
+    /// enabled if a timeout or cancellation token is set.
+    #[cfg(feature = "tokio")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "tokio")))]
+    pub async fn read_git_stub_contents_async(
+        &self,
+        stub: &GitStub,
+        repo_root: &Utf8Path,
+    ) -> Result<Vec<u8>, ReadContentsError> {
+        let cmd = self.read_contents_command(stub, repo_root);
+        let output = self.output_async(cmd).await;
+        self.read_contents_result(stub, repo_root, output)
+    }
 
-        match &self.0 {
+    /// Returns the command to read the contents referenced by `stub`.
+    fn read_contents_command(
+        &self,
+        stub: &GitStub,
+        repo_root: &Utf8Path,
+    ) -> Command {
+        let mut cmd = self.command(repo_root);
+        match &self.kind {
             VcsKind::Git { .. } => {
                 // git cat-file blob <commit>:<path>
                 cmd.args(["cat-file", "blob"]).arg(stub.to_string());
@@ -286,14 +618,34 @@ impl Vcs {
                 cmd.arg("--").arg(stub.path().as_str());
             }
         }
+        cmd
+    }
 
-        let output =
-            cmd.output().map_err(|source| ReadContentsError::SpawnFailed {
+    /// Converts the output of [`Self::read_contents_command`] into the
+    /// contents referenced by `stub`.
+    fn read_contents_result(
+        &self,
+        stub: &GitStub,
+        repo_root: &Utf8Path,
+        output: Result<Output, WaitError>,
+    ) -> Result<Vec<u8>, ReadContentsError> {
+        let vcs_name = self.name();
+        let output = output.map_err(|error| match error {
+            WaitError::Io(source) => ReadContentsError::SpawnFailed {
                 vcs_name,
-                binary_path,
+                binary_path: self.binary().to_owned(),
                 repo_root: repo_root.to_owned(),
                 source,
-            })?;
+            },
+            WaitError::TimedOut(timeout) => ReadContentsError::TimedOut {
+                vcs_name,
+                stub: stub.clone(),
+                timeout,
+            },
+            WaitError::Cancelled => {
+                ReadContentsError::Cancelled { vcs_name, stub: stub.clone() }
+            }
+        })?;
 
         if output.status.success() {
             Ok(output.stdout)
@@ -307,14 +659,666 @@ impl Vcs {
             })
         }
     }
+
+    /// Reads the blob staged in the Git index for `path` (relative to
+    /// `repo_root`).
+    ///
+    /// Runs `git cat-file --batch` with `:<path>` as input. Returns
+    /// `Ok(None)` if `path` is not in the index.
+    ///
+    /// Jujutsu does not have an index, so this returns
+    /// [`ReadIndexError::Unsupported`] for jj.
+    pub fn read_index_blob(
+        &self,
+        repo_root: &Utf8Path,
+        path: &Utf8Path,
+    ) -> Result<Option<Vec<u8>>, ReadIndexError> {
+        let VcsKind::Git { binary } = &self.kind else {
+            return Err(ReadIndexError::Unsupported { vcs_name: self.name() });
+        };
+        let unexpected = |message: String| ReadIndexError::UnexpectedOutput {
+            vcs_name: VcsName::Git,
+            path: path.to_owned(),
+            message,
+        };
+        if path.as_str().contains('\n') {
+            return Err(unexpected("path contains a newline".to_owned()));
+        }
+
+        let input = format!(":{path}\n");
+        let output = self
+            .output(
+                self.command(repo_root).args(["cat-file", "--batch"]),
+                Some(input.as_bytes()),
+            )
+            .map_err(|error| match error {
+                WaitError::Io(source) => ReadIndexError::SpawnFailed {
+                    vcs_name: VcsName::Git,
+                    binary_path: binary.clone(),
+                    repo_root: repo_root.to_owned(),
+                    source,
+                },
+                WaitError::TimedOut(timeout) => ReadIndexError::TimedOut {
+                    vcs_name: VcsName::Git,
+                    path: path.to_owned(),
+                    timeout,
+                },
+                WaitError::Cancelled => ReadIndexError::Cancelled {
+                    vcs_name: VcsName::Git,
+                    path: path.to_owned(),
+                },
+            })?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(ReadIndexError::VcsFailed {
+                vcs_name: VcsName::Git,
+                path: path.to_owned(),
+                exit_status: output.status.to_string(),
+                stderr: stderr.trim().to_string(),
+            });
+        }
+
+        // The output is either `<input> missing`, or `<oid> blob <size>`
+        // followed by the contents and a trailing newline.
+        let stdout = output.stdout;
+        let header_end =
+            stdout.iter().position(|&b| b == b'\n').ok_or_else(|| {
+                unexpected(format!(
+                    "missing header line: {:?}",
+                    String::from_utf8_lossy(&stdout)
+                ))
+            })?;
+        let header = String::from_utf8_lossy(&stdout[..header_end]);
+        if header.ends_with(" missing") {
+            return Ok(None);
+        }
+        let size = match header.split(' ').collect::<Vec<_>>()[..] {
+            [_, "blob", size] => size.parse::<usize>().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| unexpected(format!("unexpected header {header:?}")))?;
+
+        let contents_start = header_end + 1;
+        stdout
+            .get(contents_start..contents_start + size)
+            .map(|contents| Some(contents.to_vec()))
+            .ok_or_else(|| {
+                unexpected(format!(
+                    "expected {size} bytes of contents, got {}",
+                    stdout.len() - contents_start
+                ))
+            })
+    }
+
+    /// Returns whether `commit` exists in the repository.
+    ///
+    /// For Git, runs `git rev-parse --verify`. For Jujutsu, runs `jj log`
+    /// with the `present(<commit>)` revset.
+    pub fn commit_exists(
+        &self,
+        repo_root: &Utf8Path,
+        commit: GitCommitHash,
+    ) -> Result<bool, VcsCommandError> {
+        self.revision_exists(repo_root, &commit.to_string())
+    }
+
+    /// Returns whether `revision` (in the syntax of the VCS) resolves to a
+    /// commit.
+    pub(crate) fn revision_exists(
+        &self,
+        repo_root: &Utf8Path,
+        revision: &str,
+    ) -> Result<bool, VcsCommandError> {
+        match &self.kind {
+            VcsKind::Git { .. } => {
+                let object = format!("{revision}^{{commit}}");
+                let args = ["rev-parse", "--verify", "--quiet", &object];
+                let output = self.run_output(repo_root, &args, None)?;
+                match output.status.code() {
+                    Some(0) => Ok(true),
+                    Some(1) => Ok(false),
+                    _ => Err(self.command_failed(&args, &output)),
+                }
+            }
+            VcsKind::Jj { .. } => {
+                let revset = format!("present({revision})");
+                self.jj_revset_is_empty(repo_root, &revset).map(|empty| !empty)
+            }
+        }
+    }
+
+    /// Resolves `revision` (in the syntax of the VCS) to a single commit.
+    ///
+    /// For Git, runs `git rev-parse --verify`. For Jujutsu, runs `jj log`
+    /// with the `present(<revision>)` revset, and returns an error if the
+    /// revset contains more than one commit.
+    ///
+    /// Returns `Ok(None)` if `revision` doesn't exist.
+    pub fn resolve_commit(
+        &self,
+        repo_root: &Utf8Path,
+        revision: &str,
+    ) -> Result<Option<GitCommitHash>, VcsCommandError> {
+        match &self.kind {
+            VcsKind::Git { .. } => {
+                let object = format!("{revision}^{{commit}}");
+                let args = ["rev-parse", "--verify", "--quiet", &object];
+                let output = self.run_output(repo_root, &args, None)?;
+                match output.status.code() {
+                    Some(0) => {
+                        self.parse_commits(&args, revision, &output.stdout)
+                    }
+                    Some(1) => Ok(None),
+                    _ => Err(self.command_failed(&args, &output)),
+                }
+            }
+            VcsKind::Jj { .. } => {
+                let revset = format!("present({revision})");
+                let args = [
+                    "log",
+                    "--ignore-working-copy",
+                    "--no-graph",
+                    "--revisions",
+                    &revset,
+                    "--template",
+                    "commit_id ++ \"\\n\"",
+                ];
+                let stdout = self.run(repo_root, &args, None)?;
+                self.parse_commits(&args, revision, &stdout)
+            }
+        }
+    }
+
+    /// Parses output with at most one commit hash per line, for
+    /// [`Vcs::resolve_commit`].
+    fn parse_commits(
+        &self,
+        args: &[&str],
+        revision: &str,
+        stdout: &[u8],
+    ) -> Result<Option<GitCommitHash>, VcsCommandError> {
+        match &split_output(stdout, b'\n')[..] {
+            [] => Ok(None),
+            [commit] => commit.trim().parse().map(Some).map_err(|_| {
+                self.unexpected_output(
+                    args,
+                    format!("invalid commit hash {commit:?}"),
+                )
+            }),
+            commits => Err(self.unexpected_output(
+                args,
+                format!(
+                    "{revision:?} resolves to {} commits, expected 1",
+                    commits.len()
+                ),
+            )),
+        }
+    }
+
+    /// Returns whether `commit` is an ancestor of (or the same as)
+    /// `revision`.
+    ///
+    /// `revision` uses the syntax of the VCS: for example, `origin/main` for
+    /// Git, or `main@origin` for Jujutsu.
+    ///
+    /// For Git, runs `git merge-base --is-ancestor`. For Jujutsu, runs
+    /// `jj log` with the `<commit> & ::(<revision>)` revset.
+    ///
+    /// Returns an error if either `commit` or `revision` doesn't exist.
+    pub fn is_ancestor(
+        &self,
+        repo_root: &Utf8Path,
+        commit: GitCommitHash,
+        revision: &str,
+    ) -> Result<bool, VcsCommandError> {
+        match &self.kind {
+            VcsKind::Git { .. } => {
+                let commit = commit.to_string();
+                let args = ["merge-base", "--is-ancestor", &commit, revision];
+                let output = self.run_output(repo_root, &args, None)?;
+                match output.status.code() {
+                    Some(0) => Ok(true),
+                    Some(1) => Ok(false),
+                    _ => Err(self.command_failed(&args, &output)),
+                }
+            }
+            VcsKind::Jj { .. } => {
+                let revset = format!("{commit} & ::({revision})");
+                self.jj_revset_is_empty(repo_root, &revset).map(|empty| !empty)
+            }
+        }
+    }
+
+    /// Returns whether `path` (relative to the repository root) is a tracked
+    /// file in the working tree.
+    ///
+    /// For Git, runs `git ls-files --error-unmatch`, which checks the index.
+    /// For Jujutsu, runs `jj file list` for the working-copy commit, without
+    /// snapshotting the working copy.
+    pub(crate) fn is_tracked(
+        &self,
+        repo_root: &Utf8Path,
+        path: &Utf8Path,
+    ) -> Result<bool, VcsCommandError> {
+        match &self.kind {
+            VcsKind::Git { .. } => {
+                // Match the path exactly, rather than as a glob.
+                let pathspec = format!(":(literal){path}");
+                let args = ["ls-files", "--error-unmatch", "--", &pathspec];
+                let output = self.run_output(repo_root, &args, None)?;
+                match output.status.code() {
+                    Some(0) => Ok(true),
+                    Some(1) => Ok(false),
+                    _ => Err(self.command_failed(&args, &output)),
+                }
+            }
+            VcsKind::Jj { .. } => {
+                // Match the path exactly, rather than as a fileset
+                // expression.
+                let fileset = format!("root-file:{:?}", path);
+                let stdout = self.run(
+                    repo_root,
+                    &[
+                        "file",
+                        "list",
+                        "--ignore-working-copy",
+                        "--revision",
+                        "@",
+                        "--",
+                        &fileset,
+                    ],
+                    None,
+                )?;
+                Ok(!stdout.is_empty())
+            }
+        }
+    }
+
+    /// Returns the revision for the commit checked out in the working tree:
+    /// `HEAD` for Git, or `@` for Jujutsu.
+    pub(crate) fn head_revision(&self) -> &'static str {
+        match &self.kind {
+            VcsKind::Git { .. } => "HEAD",
+            VcsKind::Jj { .. } => "@",
+        }
+    }
+
+    /// Returns whether a jj revset evaluates to no commits.
+    fn jj_revset_is_empty(
+        &self,
+        repo_root: &Utf8Path,
+        revset: &str,
+    ) -> Result<bool, VcsCommandError> {
+        let stdout = self.run(
+            repo_root,
+            &[
+                "log",
+                "--ignore-working-copy",
+                "--no-graph",
+                "--revisions",
+                revset,
+                "--template",
+                "commit_id ++ \"\\n\"",
+            ],
+            None,
+        )?;
+        Ok(stdout.iter().all(|b| b.is_ascii_whitespace()))
+    }
+
+    /// Reads the blob IDs of the files referenced by `stubs`.
+    ///
+    /// Runs `git cat-file --batch-check`. The result has one entry per stub,
+    /// in the same order, and the entry is `None` if the referenced file
+    /// doesn't exist (or isn't a regular file).
+    ///
+    /// Currently only supported for Git.
+    pub fn read_blob_ids(
+        &self,
+        repo_root: &Utf8Path,
+        stubs: &[GitStub],
+    ) -> Result<Vec<Option<String>>, VcsCommandError> {
+        self.require_git("reading blob IDs")?;
+        let args = ["cat-file", "--batch-check"];
+        let input: String =
+            stubs.iter().map(|stub| format!("{stub}\n")).collect();
+        let stdout = self.run(repo_root, &args, Some(input.as_bytes()))?;
+        let stdout = String::from_utf8_lossy(&stdout);
+
+        let blob_ids: Vec<_> = stdout
+            .lines()
+            .map(|line| match line.split(' ').collect::<Vec<_>>()[..] {
+                [blob_id, "blob", _] => Some(blob_id.to_owned()),
+                _ => None,
+            })
+            .collect();
+        if blob_ids.len() != stubs.len() {
+            return Err(self.unexpected_output(
+                &args,
+                format!(
+                    "expected {} lines of output, got {}",
+                    stubs.len(),
+                    blob_ids.len()
+                ),
+            ));
+        }
+        Ok(blob_ids)
+    }
+
+    /// Reads the contents of Git `objects` (such as blob IDs or
+    /// `<commit>:<path>` names) using `git cat-file --batch`.
+    ///
+    /// The result has one entry per object, in the same order, and the entry
+    /// is `None` if the object doesn't exist or isn't a blob.
+    pub(crate) fn read_blobs(
+        &self,
+        repo_root: &Utf8Path,
+        objects: &[String],
+    ) -> Result<Vec<Option<Vec<u8>>>, VcsCommandError> {
+        self.require_git("reading blobs")?;
+        let args = ["cat-file", "--batch"];
+        let input: String =
+            objects.iter().map(|object| format!("{object}\n")).collect();
+        let stdout = self.run(repo_root, &args, Some(input.as_bytes()))?;
+
+        // Each object is output as either `<input> missing` (or similar), or
+        // `<oid> <type> <size>` followed by the contents and a newline.
+        let mut blobs = Vec::with_capacity(objects.len());
+        let mut rest = stdout.as_slice();
+        while !rest.is_empty() {
+            let header_end =
+                rest.iter().position(|&b| b == b'\n').ok_or_else(|| {
+                    self.unexpected_output(
+                        &args,
+                        "missing header line".to_owned(),
+                    )
+                })?;
+            let header = String::from_utf8_lossy(&rest[..header_end]);
+            rest = &rest[header_end + 1..];
+            let (kind, size) = match header.split(' ').collect::<Vec<_>>()[..] {
+                [_, kind, size] => match size.parse::<usize>() {
+                    Ok(size) => (kind, size),
+                    Err(_) => {
+                        blobs.push(None);
+                        continue;
+                    }
+                },
+                _ => {
+                    blobs.push(None);
+                    continue;
+                }
+            };
+            let Some(contents) = rest.get(..size) else {
+                return Err(self.unexpected_output(
+                    &args,
+                    format!(
+                        "expected {size} bytes of contents, got {}",
+                        rest.len()
+                    ),
+                ));
+            };
+            blobs.push((kind == "blob").then(|| contents.to_vec()));
+            rest = rest.get(size + 1..).unwrap_or_default();
+        }
+        if blobs.len() != objects.len() {
+            return Err(self.unexpected_output(
+                &args,
+                format!(
+                    "expected {} objects in output, got {}",
+                    objects.len(),
+                    blobs.len()
+                ),
+            ));
+        }
+        Ok(blobs)
+    }
+
+    /// Returns an [`VcsCommandError::Unsupported`] error if this isn't Git.
+    pub(crate) fn require_git(
+        &self,
+        operation: &'static str,
+    ) -> Result<(), VcsCommandError> {
+        match &self.kind {
+            VcsKind::Git { .. } => Ok(()),
+            VcsKind::Jj { .. } => Err(VcsCommandError::Unsupported {
+                vcs_name: self.name(),
+                operation,
+            }),
+        }
+    }
+
+    /// Runs the VCS with `args` in `repo_root` and returns its stdout.
+    ///
+    /// If `stdin` is provided, it is written to the process's standard input.
+    pub(crate) fn run(
+        &self,
+        repo_root: &Utf8Path,
+        args: &[&str],
+        stdin: Option<&[u8]>,
+    ) -> Result<Vec<u8>, VcsCommandError> {
+        let output = self.run_output(repo_root, args, stdin)?;
+        if output.status.success() {
+            Ok(output.stdout)
+        } else {
+            Err(self.command_failed(args, &output))
+        }
+    }
+
+    /// Runs the VCS with `args` in `repo_root` and returns its output,
+    /// regardless of its exit status.
+    ///
+    /// If `stdin` is provided, it is written to the process's standard input.
+    pub(crate) fn run_output(
+        &self,
+        repo_root: &Utf8Path,
+        args: &[&str],
+        stdin: Option<&[u8]>,
+    ) -> Result<Output, VcsCommandError> {
+        let vcs_name = self.name();
+        self.output(self.command(repo_root).args(args), stdin).map_err(
+            |error| match error {
+                WaitError::Io(source) => VcsCommandError::SpawnFailed {
+                    vcs_name,
+                    binary_path: self.binary().to_owned(),
+                    repo_root: repo_root.to_owned(),
+                    source,
+                },
+                WaitError::TimedOut(timeout) => VcsCommandError::TimedOut {
+                    vcs_name,
+                    args: args.join(" "),
+                    timeout,
+                },
+                WaitError::Cancelled => VcsCommandError::Cancelled {
+                    vcs_name,
+                    args: args.join(" "),
+                },
+            },
+        )
+    }
+
+    /// Spawns `cmd` with piped stdout and stderr and waits for it to exit,
+    /// enforcing the timeout and cancellation token if set.
+    ///
+    /// If `stdin` is provided, it is written to the process's standard input.
+    fn output(
+        &self,
+        cmd: &mut Command,
+        stdin: Option<&[u8]>,
+    ) -> Result<Output, WaitError> {
+        if self.is_cancelled() {
+            return Err(WaitError::Cancelled);
+        }
+        let mut child = cmd
+            .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(WaitError::Io)?;
+        // Write from a separate thread so that a large input can't deadlock
+        // against a full stdout pipe. Write errors (e.g., if the process
+        // exits early) show up in the exit status.
+        let writer = match (stdin, child.stdin.take()) {
+            (Some(input), Some(mut child_stdin)) => {
+                let input = input.to_vec();
+                Some(std::thread::spawn(move || {
+                    let _ = child_stdin.write_all(&input);
+                }))
+            }
+            _ => None,
+        };
+
+        if self.timeout.is_none() && self.cancellation.is_none() {
+            let output = child.wait_with_output().map_err(WaitError::Io)?;
+            if let Some(writer) = writer {
+                let _ = writer.join();
+            }
+            return Ok(output);
+        }
+
+        // Read output from separate threads while polling for exit. If the
+        // process is killed, these threads are detached rather than joined,
+        // since its own children may still hold the pipes open.
+        let stdout = child.stdout.take().map(read_to_end);
+        let stderr = child.stderr.take().map(read_to_end);
+        let start = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(WaitError::Io)? {
+                break status;
+            }
+            let error = if self.is_cancelled() {
+                WaitError::Cancelled
+            } else if let Some(timeout) =
+                self.timeout.filter(|&timeout| start.elapsed() >= timeout)
+            {
+                WaitError::TimedOut(timeout)
+            } else {
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            };
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(error);
+        };
+
+        let join = |reader: Option<JoinHandle<io::Result<Vec<u8>>>>| {
+            reader.map_or(Ok(Vec::new()), |reader| {
+                reader.join().expect("reader thread doesn't panic")
+            })
+        };
+        let stdout = join(stdout).map_err(WaitError::Io)?;
+        let stderr = join(stderr).map_err(WaitError::Io)?;
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+        Ok(Output { status, stdout, stderr })
+    }
+
+    /// Converts an error from waiting for a shallow clone check.
+    fn shallow_clone_error(
+        &self,
+        repo_root: &Utf8Path,
+        error: WaitError,
+    ) -> ShallowCloneError {
+        let vcs_name = self.name();
+        match error {
+            WaitError::Io(source) => ShallowCloneError::SpawnFailed {
+                vcs_name,
+                binary_path: self.binary().to_owned(),
+                repo_root: repo_root.to_owned(),
+                source,
+            },
+            WaitError::TimedOut(timeout) => {
+                ShallowCloneError::TimedOut { vcs_name, timeout }
+            }
+            WaitError::Cancelled => ShallowCloneError::Cancelled { vcs_name },
+        }
+    }
+
+    /// Asynchronously spawns `cmd` with piped stdout and stderr and waits
+    /// for it to exit, enforcing the timeout and cancellation token if set.
+    #[cfg(feature = "tokio")]
+    async fn output_async(&self, cmd: Command) -> Result<Output, WaitError> {
+        if self.is_cancelled() {
+            return Err(WaitError::Cancelled);
+        }
+        let mut cmd = tokio::process::Command::from(cmd);
+        // Dropping the future returned by `wait_with_output` (on timeout or
+        // cancellation, or if the caller's future is dropped) kills the
+        // process.
+        let child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(WaitError::Io)?;
+        let timed_out = async {
+            match self.timeout {
+                Some(timeout) => {
+                    tokio::time::sleep(timeout).await;
+                    timeout
+                }
+                None => std::future::pending().await,
+            }
+        };
+        let cancelled = async {
+            match &self.cancellation {
+                Some(token) => token.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            output = child.wait_with_output() => output.map_err(WaitError::Io),
+            timeout = timed_out => Err(WaitError::TimedOut(timeout)),
+            () = cancelled => Err(WaitError::Cancelled),
+        }
+    }
+
+    /// Returns whether the cancellation token, if any, has been triggered.
+    fn is_cancelled(&self) -> bool {
+        self.cancellation.as_ref().is_some_and(|token| token.is_cancelled())
+    }
+
+    /// Returns a [`VcsCommandError::Failed`] error for a failed command.
+    pub(crate) fn command_failed(
+        &self,
+        args: &[&str],
+        output: &Output,
+    ) -> VcsCommandError {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        VcsCommandError::Failed {
+            vcs_name: self.name(),
+            args: args.join(" "),
+            exit_status: output.status.to_string(),
+            stderr: stderr.trim().to_string(),
+        }
+    }
+
+    /// Returns a [`VcsCommandError::UnexpectedOutput`] error.
+    pub(crate) fn unexpected_output(
+        &self,
+        args: &[&str],
+        message: String,
+    ) -> VcsCommandError {
+        VcsCommandError::UnexpectedOutput {
+            vcs_name: self.name(),
+            args: args.join(" "),
+            message,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::{Vcs, VcsName};
-    use crate::VcsDetectError;
+    use crate::{
+        CancellationToken, ReadContentsError, ShallowCloneError,
+        VcsCommandError, VcsDetectError,
+    };
     use camino_tempfile::Utf8TempDir;
-    use std::fs;
+    use std::{
+        fs,
+        time::{Duration, Instant},
+    };
 
     #[test]
     fn test_vcs_git_default() {
@@ -496,4 +1500,164 @@ mod tests {
         assert_eq!(jj.name(), VcsName::Jj);
         assert_eq!(jj.name().to_string(), "jj");
     }
+
+    #[test]
+    fn test_vcs_command_env_isolation() {
+        let env = |cmd: &std::process::Command, var: &str| {
+            cmd.get_envs()
+                .find(|(key, _)| *key == var)
+                .map(|(_, value)| value.map(|v| v.to_str().unwrap().to_owned()))
+        };
+        let repo_root = camino::Utf8Path::new(".");
+
+        let git = Vcs::git().unwrap();
+        assert!(git.env_isolation(), "isolation is on by default");
+        let cmd = git.command(repo_root);
+        assert_eq!(env(&cmd, "GIT_DIR"), Some(None), "GIT_DIR is removed");
+        assert_eq!(env(&cmd, "GIT_INDEX_FILE"), Some(None));
+        assert_eq!(env(&cmd, "LC_ALL"), Some(Some("C".to_owned())));
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(
+            args,
+            ["-c", "color.ui=false", "-c", "core.fsmonitor=false"],
+            "config overrides come before the subcommand"
+        );
+
+        let jj = Vcs::jj().unwrap();
+        let cmd = jj.command(repo_root);
+        assert_eq!(env(&cmd, "GIT_DIR"), Some(None));
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["--no-pager", "--color", "never"]);
+
+        let git = git.with_env_isolation(false);
+        assert!(!git.env_isolation());
+        let cmd = git.command(repo_root);
+        assert_eq!(cmd.get_envs().count(), 0, "environment is inherited");
+        assert_eq!(cmd.get_args().count(), 0);
+    }
+
+    /// Returns a VCS whose binary is a script that hangs, ignoring its
+    /// arguments.
+    #[cfg(unix)]
+    fn hanging_vcs(temp: &Utf8TempDir) -> Vcs {
+        use std::os::unix::fs::PermissionsExt;
+
+        let script = temp.path().join("hang");
+        fs::write(&script, "#!/bin/sh\nexec sleep 60\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755))
+            .unwrap();
+        Vcs::new(super::VcsKind::Git { binary: script.into_string() })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_vcs_timeout() {
+        let temp = Utf8TempDir::with_prefix("git-stub-vcs-").unwrap();
+        let timeout = Duration::from_millis(100);
+        let vcs = hanging_vcs(&temp).with_timeout(timeout);
+        let stub = format!("{}:api.json", "ab".repeat(20)).parse().unwrap();
+
+        let start = Instant::now();
+        let err = vcs.read_git_stub_contents(&stub, temp.path()).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                ReadContentsError::TimedOut { timeout: t, .. } if t == timeout
+            ),
+            "should time out, got: {err:?}"
+        );
+        assert!(
+            start.elapsed() < Duration::from_secs(30),
+            "should not wait for the process to exit"
+        );
+
+        let err =
+            vcs.run(temp.path(), &["status"], Some(b"input")).unwrap_err();
+        assert!(
+            matches!(err, VcsCommandError::TimedOut { .. }),
+            "should time out, got: {err:?}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_vcs_cancellation() {
+        let temp = Utf8TempDir::with_prefix("git-stub-vcs-").unwrap();
+        let token = CancellationToken::new();
+        let vcs = hanging_vcs(&temp).with_cancellation(token.clone());
+        let stub = format!("{}:api.json", "ab".repeat(20)).parse().unwrap();
+
+        let start = Instant::now();
+        let err = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(100));
+                token.cancel();
+            });
+            vcs.read_git_stub_contents(&stub, temp.path()).unwrap_err()
+        });
+        assert!(
+            matches!(err, ReadContentsError::Cancelled { .. }),
+            "should be cancelled, got: {err:?}"
+        );
+        assert!(start.elapsed() < Duration::from_secs(30));
+
+        // Once cancelled, new commands aren't started.
+        let err = vcs.is_shallow_clone(temp.path()).unwrap_err();
+        assert!(
+            matches!(err, ShallowCloneError::Cancelled { .. }),
+            "should be cancelled, got: {err:?}"
+        );
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    #[tokio::test]
+    async fn test_vcs_timeout_async() {
+        let temp = Utf8TempDir::with_prefix("git-stub-vcs-").unwrap();
+        let timeout = Duration::from_millis(100);
+        let vcs = hanging_vcs(&temp).with_timeout(timeout);
+        let stub = format!("{}:api.json", "ab".repeat(20)).parse().unwrap();
+
+        let start = Instant::now();
+        let err = vcs
+            .read_git_stub_contents_async(&stub, temp.path())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, ReadContentsError::TimedOut { .. }),
+            "should time out, got: {err:?}"
+        );
+        assert!(start.elapsed() < Duration::from_secs(30));
+
+        // Cancelling wakes a running command.
+        let token = CancellationToken::new();
+        let vcs = hanging_vcs(&temp).with_cancellation(token.clone());
+        let start = Instant::now();
+        let cancel = tokio::spawn({
+            let token = token.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                token.cancel();
+            }
+        });
+        let err = vcs
+            .read_git_stub_contents_async(&stub, temp.path())
+            .await
+            .unwrap_err();
+        cancel.await.unwrap();
+        assert!(
+            matches!(err, ReadContentsError::Cancelled { .. }),
+            "should be cancelled, got: {err:?}"
+        );
+        assert!(start.elapsed() < Duration::from_secs(30));
+
+        // Once cancelled, new commands aren't started.
+        let err = vcs
+            .read_git_stub_contents_async(&stub, temp.path())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, ReadContentsError::Cancelled { .. }),
+            "should be cancelled, got: {err:?}"
+        );
+    }
 }
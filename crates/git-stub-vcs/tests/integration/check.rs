@@ -0,0 +1,166 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Integration tests for finding and checking git stubs.
+
+use crate::helpers::{
+    commit_json_via_git, run_git, setup_git_repo, write_file,
+};
+use anyhow::Result;
+use git_stub::GitCommitHash;
+use git_stub_vcs::{CheckError, FindGitStubsError, Vcs};
+use std::fs;
+
+#[test]
+fn test_find_git_stubs() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+    let stub_text = format!("{commit_hash}:openapi/api.json\n");
+
+    // A committed stub, an untracked stub, and an ignored stub.
+    write_file(repo_root.join("openapi/api-v1.json.gitstub"), &stub_text)?;
+    run_git(repo_root, &["add", "."])?;
+    run_git(repo_root, &["commit", "-m", "Add stub"])?;
+    write_file(repo_root.join("top.json.gitstub"), &stub_text)?;
+    fs::create_dir_all(repo_root.join("target"))?;
+    write_file(repo_root.join(".gitignore"), "target/\n")?;
+    write_file(repo_root.join("target/ignored.json.gitstub"), &stub_text)?;
+
+    let vcs = Vcs::git()?;
+    let stub_files = vcs.find_git_stubs(repo_root)?;
+    let paths: Vec<_> =
+        stub_files.iter().map(|file| file.path().as_str()).collect();
+    assert_eq!(
+        paths,
+        ["openapi/api-v1.json.gitstub", "top.json.gitstub"],
+        "should find tracked and untracked stubs, but not ignored ones"
+    );
+    assert_eq!(stub_files[0].stub().to_file_contents(), stub_text);
+
+    // Deleted stubs are skipped.
+    fs::remove_file(repo_root.join("top.json.gitstub"))?;
+    fs::remove_file(repo_root.join("openapi/api-v1.json.gitstub"))?;
+    assert_eq!(vcs.find_git_stubs(repo_root)?, [], "deleted stubs skipped");
+
+    write_file(repo_root.join("invalid.json.gitstub"), "not a stub\n")?;
+    let result = vcs.find_git_stubs(repo_root);
+    assert!(
+        matches!(result, Err(FindGitStubsError::InvalidGitStub { .. })),
+        "invalid stubs should be an error, got: {result:?}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_find_duplicate_contents() -> Result<()> {
+    let (temp, first) = setup_git_repo()?;
+    let repo_root = temp.path();
+    let second = commit_json_via_git(
+        repo_root,
+        r#"{"name": "test-api", "version": "2.0.0"}"#,
+    )?;
+    let vcs = Vcs::git()?;
+
+    // The first version is no longer in the working tree, so a stub for it
+    // is fine.
+    write_file(
+        repo_root.join("openapi/api-v1.json.gitstub"),
+        format!("{first}:openapi/api.json\n"),
+    )?;
+    run_git(repo_root, &["add", "."])?;
+    assert_eq!(vcs.find_duplicate_contents(repo_root)?, []);
+
+    // A stub for the second version duplicates openapi/api.json, as does a
+    // copy of the first version.
+    write_file(
+        repo_root.join("openapi/api-v2.json.gitstub"),
+        format!("{second}:openapi/api.json\n"),
+    )?;
+    write_file(
+        repo_root.join("openapi/api-v1-copy.json"),
+        r#"{"name": "test-api", "version": "1.0.0"}"#,
+    )?;
+    run_git(repo_root, &["add", "."])?;
+
+    let duplicates = vcs.find_duplicate_contents(repo_root)?;
+    let summary: Vec<_> = duplicates
+        .iter()
+        .map(|duplicate| {
+            (
+                duplicate.stub_file().path().as_str(),
+                duplicate
+                    .tracked_paths()
+                    .iter()
+                    .map(|path| path.as_str())
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("openapi/api-v1.json.gitstub", vec!["openapi/api-v1-copy.json"]),
+            ("openapi/api-v2.json.gitstub", vec!["openapi/api.json"]),
+        ]
+    );
+    assert_eq!(
+        duplicates[1].blob_id(),
+        run_git(
+            repo_root,
+            &["rev-parse", &format!("{second}:openapi/api.json")]
+        )?,
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_find_unreachable_commits() -> Result<()> {
+    let (temp, first) = setup_git_repo()?;
+    let repo_root = temp.path();
+    run_git(repo_root, &["branch", "protected"])?;
+    let second = commit_json_via_git(
+        repo_root,
+        r#"{"name": "test-api", "version": "2.0.0"}"#,
+    )?;
+    let missing = "dead".repeat(10);
+    let vcs = Vcs::git()?;
+
+    let first_hash: GitCommitHash = first.parse()?;
+    let second_hash: GitCommitHash = second.parse()?;
+    assert!(vcs.commit_exists(repo_root, first_hash)?);
+    assert!(!vcs.commit_exists(repo_root, missing.parse()?)?);
+    assert!(vcs.is_ancestor(repo_root, first_hash, "protected")?);
+    assert!(!vcs.is_ancestor(repo_root, second_hash, "protected")?);
+    assert!(vcs.is_ancestor(repo_root, second_hash, "HEAD")?);
+
+    for (name, commit) in
+        [("v1", &first), ("v2", &second), ("missing", &missing)]
+    {
+        write_file(
+            repo_root.join(format!("openapi/api-{name}.json.gitstub")),
+            format!("{commit}:openapi/api.json\n"),
+        )?;
+    }
+
+    let unreachable = vcs.find_unreachable_commits(repo_root, "protected")?;
+    let summary: Vec<_> = unreachable
+        .iter()
+        .map(|u| (u.stub_file().path().as_str(), u.commit_exists()))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("openapi/api-missing.json.gitstub", false),
+            ("openapi/api-v2.json.gitstub", true),
+        ]
+    );
+
+    let result = vcs.find_unreachable_commits(repo_root, "nonexistent");
+    assert!(
+        matches!(result, Err(CheckError::RevisionNotFound { .. })),
+        "unknown protected revision should be an error, got: {result:?}"
+    );
+
+    Ok(())
+}
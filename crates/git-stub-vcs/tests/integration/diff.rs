@@ -0,0 +1,74 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Integration tests for diffing git stub contents.
+
+use crate::helpers::{commit_json_via_git, setup_git_repo, write_file};
+use anyhow::Result;
+use camino::Utf8PathBuf;
+use git_stub::GitStub;
+use git_stub_vcs::{DiffError, DiffSource, Vcs};
+
+fn stub(commit_hash: &str) -> Result<DiffSource> {
+    let stub: GitStub = format!("{commit_hash}:openapi/api.json").parse()?;
+    Ok(stub.into())
+}
+
+#[test]
+fn test_diff_git_stubs() -> Result<()> {
+    let (temp, first) = setup_git_repo()?;
+    let repo_root = temp.path();
+    let second = commit_json_via_git(
+        repo_root,
+        "{\"name\": \"test-api\", \"version\": \"2.0.0\"}\n",
+    )?;
+    let vcs = Vcs::git()?;
+
+    let diff = vcs.diff(repo_root, &stub(&first)?, &stub(&first)?)?;
+    assert!(diff.is_identical(), "same stub should be identical");
+    assert_eq!(diff.unified_diff(), "", "identical diff should be empty");
+
+    let diff = vcs.diff(repo_root, &stub(&first)?, &stub(&second)?)?;
+    assert!(!diff.is_identical(), "different versions should differ");
+    let expected = format!(
+        "--- {first}:openapi/api.json\n\
+         +++ {second}:openapi/api.json\n\
+         @@ -1 +1 @@\n\
+         -{{\"name\": \"test-api\", \"version\": \"1.0.0\"}}\n\
+         \\ No newline at end of file\n\
+         +{{\"name\": \"test-api\", \"version\": \"2.0.0\"}}\n"
+    );
+    assert_eq!(diff.unified_diff(), expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_diff_git_stub_and_file() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+    let vcs = Vcs::git()?;
+    let file = DiffSource::File(Utf8PathBuf::from("openapi/api.json"));
+
+    let diff = vcs.diff(repo_root, &stub(&commit_hash)?, &file)?;
+    assert!(diff.is_identical(), "unchanged file should be identical");
+
+    write_file(repo_root.join("openapi/api.json"), [0xff, 0xfe])?;
+    let diff = vcs.diff(repo_root, &stub(&commit_hash)?, &file)?;
+    assert!(!diff.is_identical(), "modified file should differ");
+    assert_eq!(
+        diff.unified_diff(),
+        format!(
+            "Binary files {commit_hash}:openapi/api.json and \
+             openapi/api.json differ\n"
+        ),
+    );
+
+    let missing = DiffSource::File(Utf8PathBuf::from("missing.json"));
+    let result = vcs.diff(repo_root, &stub(&commit_hash)?, &missing);
+    assert!(
+        matches!(result, Err(DiffError::ReadFile { .. })),
+        "missing file should fail, got: {result:?}"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,209 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Integration tests for the Git filter process and diff driver.
+
+use crate::helpers::{run_git, setup_git_repo, write_file};
+use anyhow::Result;
+use camino::Utf8Path;
+use git_stub_vcs::{FilterProcess, FilterRequestError, Vcs, textconv};
+
+const CONTENTS: &str = r#"{"name": "test-api", "version": "1.0.0"}"#;
+
+/// Stages a git stub pointing at `openapi/api.json` as
+/// `openapi/api-v1.json`, and returns the stub text.
+fn stage_stub(repo_root: &Utf8Path, commit_hash: &str) -> Result<String> {
+    let stub_text = format!("{commit_hash}:openapi/api.json\n");
+    write_file(repo_root.join("openapi/api-v1.json"), &stub_text)?;
+    run_git(repo_root, &["add", "openapi/api-v1.json"])?;
+    Ok(stub_text)
+}
+
+fn packet(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(format!("{:04x}", data.len() + 4).as_bytes());
+    buf.extend_from_slice(data);
+}
+
+fn flush(buf: &mut Vec<u8>) {
+    buf.extend_from_slice(b"0000");
+}
+
+fn request(buf: &mut Vec<u8>, command: &str, pathname: &str, contents: &[u8]) {
+    packet(buf, format!("command={command}\n").as_bytes());
+    packet(buf, format!("pathname={pathname}\n").as_bytes());
+    flush(buf);
+    if !contents.is_empty() {
+        packet(buf, contents);
+    }
+    flush(buf);
+}
+
+/// Splits pkt-line output into packets, with `None` for flush packets.
+fn parse_packets(mut output: &[u8]) -> Vec<Option<Vec<u8>>> {
+    let mut packets = Vec::new();
+    while !output.is_empty() {
+        let len = std::str::from_utf8(&output[..4]).unwrap();
+        let len = usize::from_str_radix(len, 16).unwrap();
+        if len == 0 {
+            packets.push(None);
+            output = &output[4..];
+        } else {
+            packets.push(Some(output[4..len].to_vec()));
+            output = &output[len..];
+        }
+    }
+    packets
+}
+
+#[test]
+fn test_filter_smudge() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+    let filter = FilterProcess::new(repo_root)?;
+
+    let stub_text = format!("{commit_hash}:openapi/api.json\n");
+    let smudged =
+        filter.smudge("openapi/api-v1.json".into(), stub_text.as_bytes())?;
+    assert_eq!(smudged, CONTENTS.as_bytes(), "stub should be expanded");
+
+    let smudged = filter.smudge("other.json".into(), b"not a stub\n")?;
+    assert_eq!(smudged, b"not a stub\n", "non-stubs should pass through");
+
+    let missing = format!("{}:openapi/api.json\n", "dead".repeat(10));
+    let result = filter.smudge("missing.json".into(), missing.as_bytes());
+    assert!(
+        matches!(result, Err(FilterRequestError::ReadContents { .. })),
+        "unknown commit should fail, got: {result:?}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_filter_clean() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+    let stub_text = stage_stub(repo_root, &commit_hash)?;
+    let filter = FilterProcess::new(repo_root)?;
+    let pathname = Utf8Path::new("openapi/api-v1.json");
+
+    let cleaned = filter.clean(pathname, CONTENTS.as_bytes())?;
+    assert_eq!(
+        cleaned,
+        stub_text.as_bytes(),
+        "expanded contents should collapse to the stub in the index"
+    );
+
+    let new_stub = format!("{}:openapi/other.json\n", "ab".repeat(20));
+    let cleaned = filter.clean(pathname, new_stub.as_bytes())?;
+    assert_eq!(
+        cleaned,
+        new_stub.as_bytes(),
+        "stub text should pass through unchanged"
+    );
+
+    let result = filter.clean(pathname, b"{}");
+    assert!(
+        matches!(result, Err(FilterRequestError::ContentsModified { .. })),
+        "modified contents should be rejected, got: {result:?}"
+    );
+
+    // Files that aren't stubs in the index are passed through.
+    let cleaned = filter.clean("openapi/api.json".into(), b"{}")?;
+    assert_eq!(cleaned, b"{}", "regular files should pass through");
+    let cleaned = filter.clean("untracked.json".into(), b"{}")?;
+    assert_eq!(cleaned, b"{}", "untracked files should pass through");
+
+    Ok(())
+}
+
+#[test]
+fn test_filter_process_protocol() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+    let stub_text = stage_stub(repo_root, &commit_hash)?;
+    let filter = FilterProcess::new(repo_root)?;
+
+    let mut input = Vec::new();
+    packet(&mut input, b"git-filter-client\n");
+    packet(&mut input, b"version=2\n");
+    flush(&mut input);
+    packet(&mut input, b"capability=clean\n");
+    packet(&mut input, b"capability=smudge\n");
+    flush(&mut input);
+    request(&mut input, "smudge", "openapi/api-v1.json", stub_text.as_bytes());
+    request(&mut input, "clean", "openapi/api-v1.json", CONTENTS.as_bytes());
+    request(&mut input, "clean", "openapi/api-v1.json", b"modified");
+    // Unknown commands fail the request without ending the session.
+    request(&mut input, "frobnicate", "openapi/api-v1.json", b"");
+    request(&mut input, "smudge", "openapi/api-v1.json", stub_text.as_bytes());
+
+    let mut output = Vec::new();
+    let mut errors = Vec::new();
+    filter.run(input.as_slice(), &mut output, |error| errors.push(error))?;
+
+    let success = |contents: &[u8]| {
+        vec![
+            Some(b"status=success\n".to_vec()),
+            None,
+            Some(contents.to_vec()),
+            None,
+            None,
+        ]
+    };
+    let expected: Vec<_> = [
+        vec![
+            Some(b"git-filter-server\n".to_vec()),
+            Some(b"version=2\n".to_vec()),
+            None,
+            Some(b"capability=clean\n".to_vec()),
+            Some(b"capability=smudge\n".to_vec()),
+            None,
+        ],
+        success(CONTENTS.as_bytes()),
+        success(stub_text.as_bytes()),
+        vec![Some(b"status=error\n".to_vec()), None],
+        vec![Some(b"status=error\n".to_vec()), None],
+        success(CONTENTS.as_bytes()),
+    ]
+    .concat();
+    assert_eq!(parse_packets(&output), expected);
+
+    assert_eq!(errors.len(), 2, "errors: {errors:?}");
+    assert!(
+        matches!(errors[0], FilterRequestError::ContentsModified { .. }),
+        "modified contents should be reported, got: {:?}",
+        errors[0]
+    );
+    assert!(
+        matches!(
+            &errors[1],
+            FilterRequestError::UnknownCommand { command, .. }
+                if command == "frobnicate"
+        ),
+        "unknown command should be reported, got: {:?}",
+        errors[1]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_textconv() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+    let vcs = Vcs::git()?;
+
+    let stub_text = format!("{commit_hash}:openapi/api.json\n");
+    let converted = textconv(&vcs, repo_root, stub_text.as_bytes())?;
+    assert_eq!(converted, CONTENTS.as_bytes(), "stub should be converted");
+
+    let conflicted = "<<<<<<< HEAD\n=======\n>>>>>>> branch\n";
+    let converted = textconv(&vcs, repo_root, conflicted.as_bytes())?;
+    assert_eq!(
+        converted,
+        conflicted.as_bytes(),
+        "non-stubs should pass through"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,210 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Shared helpers for integration tests.
+
+use anyhow::Result;
+use atomicwrites::{AtomicFile, OverwriteBehavior};
+use camino::Utf8Path;
+use camino_tempfile::Utf8TempDir;
+use std::{fs, io::Write, process::Command};
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+/// Returns a `Command` for git, respecting the `$GIT` environment variable.
+pub(crate) fn git_command() -> Command {
+    let bin = std::env::var("GIT").unwrap_or_else(|_| "git".to_string());
+    Command::new(bin)
+}
+
+/// Runs git with `args` in `repo_root`, returning its trimmed stdout.
+pub(crate) fn run_git(repo_root: &Utf8Path, args: &[&str]) -> Result<String> {
+    let output = git_command().args(args).current_dir(repo_root).output()?;
+    assert!(
+        output.status.success(),
+        "git {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// Returns a `Command` for jj, respecting the `$JJ` environment variable.
+pub(crate) fn jj_command() -> Command {
+    let bin = std::env::var("JJ").unwrap_or_else(|_| "jj".to_string());
+    Command::new(bin)
+}
+
+/// Writes content to a file atomically.
+pub(crate) fn write_file(
+    path: impl AsRef<Utf8Path>,
+    content: impl AsRef<[u8]>,
+) -> std::io::Result<()> {
+    let path = path.as_ref();
+    AtomicFile::new(path, OverwriteBehavior::AllowOverwrite)
+        .write(|f| f.write_all(content.as_ref()))
+        .map_err(|e| e.into())
+}
+
+/// Returns `Ok(true)` if jj is available, `Ok(false)` if `SKIP_JJ_TESTS`
+/// is set, or `Err` if jj is not found.
+pub(crate) fn check_jj_available() -> Result<bool> {
+    if std::env::var("SKIP_JJ_TESTS").is_ok() {
+        return Ok(false);
+    }
+
+    match jj_command().arg("--version").output() {
+        Ok(o) if o.status.success() => Ok(true),
+        Ok(o) => Err(anyhow::anyhow!(
+            "jj --version failed ({}): {}. \
+             Set SKIP_JJ_TESTS=1 to skip these tests",
+            o.status,
+            String::from_utf8_lossy(&o.stderr).trim(),
+        )),
+        Err(e) => Err(anyhow::anyhow!(
+            "jj not found ({e}). Install jj \
+             (https://jj-vcs.dev/) or set SKIP_JJ_TESTS=1 to \
+             skip these tests"
+        )),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Repository setup helpers
+// ---------------------------------------------------------------------------
+
+/// Initializes a git repository and configures the user.
+pub(crate) fn init_git_repo(repo_root: &Utf8Path) -> Result<()> {
+    let status =
+        git_command().args(["init"]).current_dir(repo_root).status()?;
+    assert!(status.success(), "git init failed");
+
+    let status = git_command()
+        .args(["config", "user.email", "test@example.com"])
+        .current_dir(repo_root)
+        .status()?;
+    assert!(status.success(), "git config user.email failed");
+
+    let status = git_command()
+        .args(["config", "user.name", "Test User"])
+        .current_dir(repo_root)
+        .status()?;
+    assert!(status.success(), "git config user.name failed");
+
+    Ok(())
+}
+
+/// Creates a JSON file and commits it via git.
+/// Returns the commit hash.
+pub(crate) fn commit_json_via_git(
+    repo_root: &Utf8Path,
+    contents: &str,
+) -> Result<String> {
+    let json_path = repo_root.join("openapi").join("api.json");
+    fs::create_dir_all(json_path.parent().unwrap())?;
+    write_file(&json_path, contents)?;
+
+    let status =
+        git_command().args(["add", "."]).current_dir(repo_root).status()?;
+    assert!(status.success(), "git add failed");
+
+    let status = git_command()
+        .args(["commit", "-m", "Add API spec"])
+        .current_dir(repo_root)
+        .status()?;
+    assert!(status.success(), "git commit failed");
+
+    let output = git_command()
+        .args(["rev-parse", "HEAD"])
+        .current_dir(repo_root)
+        .output()?;
+    assert!(
+        output.status.success(),
+        "git rev-parse HEAD failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// Creates a JSON file and commits it via jj.
+/// Returns the commit hash.
+pub(crate) fn commit_json_via_jj(
+    repo_root: &Utf8Path,
+    contents: &str,
+) -> Result<String> {
+    let json_path = repo_root.join("openapi").join("api.json");
+    fs::create_dir_all(json_path.parent().unwrap())?;
+    write_file(&json_path, contents)?;
+
+    let status = jj_command()
+        .args(["commit", "-m", "Add API spec"])
+        .current_dir(repo_root)
+        .status()?;
+    assert!(status.success(), "jj commit failed");
+
+    let output = jj_command()
+        .args(["log", "-r", "@-", "--no-graph", "-T", "commit_id"])
+        .current_dir(repo_root)
+        .output()?;
+    assert!(
+        output.status.success(),
+        "jj log failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// Sets up a temporary git repository with a committed JSON file.
+/// Returns (temp_dir, commit_hash).
+pub(crate) fn setup_git_repo() -> Result<(Utf8TempDir, String)> {
+    let temp = Utf8TempDir::with_prefix("git-stub-materialize-")?;
+    let repo_root = temp.path();
+
+    init_git_repo(repo_root)?;
+    let commit_hash = commit_json_via_git(
+        repo_root,
+        r#"{"name": "test-api", "version": "1.0.0"}"#,
+    )?;
+
+    Ok((temp, commit_hash))
+}
+
+/// Sets up a temporary jj-colocated repository with a committed JSON
+/// file. Returns (temp_dir, commit_hash).
+pub(crate) fn setup_jj_colocated_repo() -> Result<(Utf8TempDir, String)> {
+    let temp = Utf8TempDir::with_prefix("git-stub-materialize-jj-")?;
+    let repo_root = temp.path();
+
+    let status = jj_command()
+        .args(["git", "init", "--colocate"])
+        .current_dir(repo_root)
+        .status()?;
+    assert!(status.success(), "jj git init --colocate failed");
+
+    let commit_hash = commit_json_via_jj(
+        repo_root,
+        r#"{"name": "test-api", "version": "2.0.0"}"#,
+    )?;
+
+    Ok((temp, commit_hash))
+}
+
+/// Sets up a non-colocated jj repo (`.jj` but no `.git`).
+pub(crate) fn setup_jj_non_colocated_repo() -> Result<(Utf8TempDir, String)> {
+    let temp = Utf8TempDir::with_prefix("git-stub-materialize-jj-noncoloc-")?;
+    let repo_root = temp.path();
+
+    let status = jj_command()
+        .args(["git", "init", "--no-colocate"])
+        .current_dir(repo_root)
+        .status()?;
+    assert!(status.success(), "jj git init --no-colocate failed");
+
+    let commit_hash = commit_json_via_jj(
+        repo_root,
+        r#"{"name": "test-api", "version": "3.0.0"}"#,
+    )?;
+
+    Ok((temp, commit_hash))
+}
@@ -0,0 +1,83 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Integration tests for the git stub index.
+
+use crate::helpers::{
+    commit_json_via_git, run_git, setup_git_repo, write_file,
+};
+use anyhow::Result;
+use camino::Utf8Path;
+use git_stub::GitCommitHash;
+use git_stub_vcs::{GitStubIndex, Vcs};
+use std::fs;
+
+fn paths<'a>(
+    stub_files: impl Iterator<Item = &'a git_stub_vcs::GitStubFile>,
+) -> Vec<&'a str> {
+    stub_files.map(|file| file.path().as_str()).collect()
+}
+
+#[test]
+fn test_git_stub_index() -> Result<()> {
+    let (temp, first) = setup_git_repo()?;
+    let repo_root = temp.path();
+    let second = commit_json_via_git(
+        repo_root,
+        r#"{"name": "test-api", "version": "2.0.0"}"#,
+    )?;
+
+    fs::create_dir_all(repo_root.join("archive"))?;
+    write_file(
+        repo_root.join("openapi/api-v1.json.gitstub"),
+        format!("{first}:openapi/api.json\n"),
+    )?;
+    write_file(
+        repo_root.join("archive/api-v1.json.gitstub"),
+        format!("{first}:openapi/api.json\n"),
+    )?;
+    write_file(
+        repo_root.join("archive/readme.md.gitstub"),
+        format!("{second}:README.md\n"),
+    )?;
+    run_git(repo_root, &["add", "."])?;
+    run_git(repo_root, &["commit", "-m", "Add stubs"])?;
+    let stubs_commit = run_git(repo_root, &["rev-parse", "HEAD"])?;
+    fs::remove_file(repo_root.join("archive/readme.md.gitstub"))?;
+
+    let vcs = Vcs::git()?;
+    let first_hash: GitCommitHash = first.parse()?;
+    let second_hash: GitCommitHash = second.parse()?;
+
+    // The working tree no longer has the README stub.
+    let index = GitStubIndex::new(vcs.find_git_stubs(repo_root)?);
+    assert_eq!(
+        paths(index.by_commit(first_hash)),
+        ["archive/api-v1.json.gitstub", "openapi/api-v1.json.gitstub"]
+    );
+    assert_eq!(paths(index.by_commit(second_hash)), [] as [&str; 0]);
+    assert_eq!(index.commits().collect::<Vec<_>>(), [first_hash]);
+
+    // At the commit, it does.
+    let index = GitStubIndex::new(vcs.find_git_stubs_at(repo_root, "HEAD")?);
+    assert_eq!(index.stub_files().len(), 3);
+    assert_eq!(
+        paths(index.by_commit(second_hash)),
+        ["archive/readme.md.gitstub"]
+    );
+    assert_eq!(
+        paths(index.by_path(Utf8Path::new("openapi/api.json"))),
+        ["archive/api-v1.json.gitstub", "openapi/api-v1.json.gitstub"]
+    );
+    assert_eq!(
+        index.paths().collect::<Vec<_>>(),
+        ["README.md", "openapi/api.json"]
+    );
+
+    // Commits before the stubs were added have none.
+    let stub_files = vcs.find_git_stubs_at(repo_root, &second)?;
+    assert_eq!(stub_files, []);
+    let stub_files = vcs.find_git_stubs_at(repo_root, &stubs_commit)?;
+    assert_eq!(stub_files.len(), 3);
+
+    Ok(())
+}
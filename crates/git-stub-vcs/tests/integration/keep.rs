@@ -0,0 +1,71 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Integration tests for keep-alive refs.
+
+use crate::helpers::{
+    commit_json_via_git, run_git, setup_git_repo, write_file,
+};
+use anyhow::Result;
+use git_stub_vcs::{KEEP_REF_PREFIX, Vcs};
+use std::fs;
+
+#[test]
+fn test_update_keep_refs() -> Result<()> {
+    let (temp, first) = setup_git_repo()?;
+    let repo_root = temp.path();
+    let second = commit_json_via_git(
+        repo_root,
+        r#"{"name": "test-api", "version": "2.0.0"}"#,
+    )?;
+    let missing = "dead".repeat(10);
+    let vcs = Vcs::git()?;
+
+    for (name, commit) in [("v1", &first), ("v1-copy", &first), ("v2", &second)]
+    {
+        write_file(
+            repo_root.join(format!("openapi/api-{name}.json.gitstub")),
+            format!("{commit}:openapi/api.json\n"),
+        )?;
+    }
+    write_file(
+        repo_root.join("openapi/api-missing.json.gitstub"),
+        format!("{missing}:openapi/api.json\n"),
+    )?;
+
+    let update = vcs.update_keep_refs(repo_root)?;
+    let mut expected = vec![
+        format!("{KEEP_REF_PREFIX}{first}"),
+        format!("{KEEP_REF_PREFIX}{second}"),
+    ];
+    expected.sort();
+    assert_eq!(update.created(), expected);
+    assert_eq!(update.deleted(), [] as [String; 0]);
+    let missing_paths: Vec<_> =
+        update.missing().iter().map(|file| file.path().as_str()).collect();
+    assert_eq!(missing_paths, ["openapi/api-missing.json.gitstub"]);
+    assert_eq!(
+        run_git(
+            repo_root,
+            &["rev-parse", &format!("{KEEP_REF_PREFIX}{first}")]
+        )?,
+        first,
+    );
+
+    // Running again is a no-op.
+    let update = vcs.update_keep_refs(repo_root)?;
+    assert_eq!(update.created(), [] as [String; 0]);
+    assert_eq!(update.deleted(), [] as [String; 0]);
+
+    // Refs for commits that are no longer referenced are pruned.
+    fs::remove_file(repo_root.join("openapi/api-v2.json.gitstub"))?;
+    let update = vcs.update_keep_refs(repo_root)?;
+    assert_eq!(update.created(), [] as [String; 0]);
+    assert_eq!(update.deleted(), [format!("{KEEP_REF_PREFIX}{second}")]);
+    let refs = run_git(
+        repo_root,
+        &["for-each-ref", "--format=%(refname)", KEEP_REF_PREFIX],
+    )?;
+    assert_eq!(refs, format!("{KEEP_REF_PREFIX}{first}"));
+
+    Ok(())
+}
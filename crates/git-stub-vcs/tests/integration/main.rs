@@ -1,3 +1,10 @@
 // Copyright 2026 Oxide Computer Company
 
+mod check;
+mod diff;
+mod filter;
+mod helpers;
+mod index;
+mod keep;
 mod materialize;
+mod repin;
@@ -2,234 +2,757 @@
 
 //! Integration tests for git-stub-vcs.
 
+use crate::helpers::{
+    check_jj_available, commit_json_via_git, git_command, jj_command, run_git,
+    setup_git_repo, setup_jj_colocated_repo, setup_jj_non_colocated_repo,
+    write_file,
+};
 use anyhow::Result;
-use atomicwrites::{AtomicFile, OverwriteBehavior};
-use camino::Utf8Path;
+use camino::Utf8PathBuf;
 use camino_tempfile::Utf8TempDir;
 use git_stub::GitStub;
 use git_stub_vcs::{
-    MaterializeError, Materializer, ReadContentsError, Vcs, VcsName,
+    DirectiveSink, MaterializeAllError, MaterializeError, Materializer,
+    ReadContentsError, ResolveChainError, Vcs, VcsDetectError, VcsName,
+    WriteDepfileError, WriteModuleError,
+};
+use std::{
+    fs,
+    num::NonZeroUsize,
+    time::{Duration, SystemTime},
 };
-use std::{fs, io::Write, process::Command};
 
-// ---------------------------------------------------------------------------
-// Test helpers
-// ---------------------------------------------------------------------------
+#[test]
+fn test_materialize_git_stub() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
 
-/// Returns a `Command` for git, respecting the `$GIT` environment variable.
-fn git_command() -> Command {
-    let bin = std::env::var("GIT").unwrap_or_else(|_| "git".to_string());
-    Command::new(bin)
-}
+    let git_stub_content = format!("{}:openapi/api.json\n", commit_hash);
+    let git_stub_path = repo_root.join("openapi").join("api.json.gitstub");
+    write_file(&git_stub_path, &git_stub_content)?;
 
-/// Returns a `Command` for jj, respecting the `$JJ` environment variable.
-fn jj_command() -> Command {
-    let bin = std::env::var("JJ").unwrap_or_else(|_| "jj".to_string());
-    Command::new(bin)
-}
+    let output_dir = repo_root.join("out");
+    fs::create_dir_all(&output_dir)?;
 
-/// Writes content to a file atomically.
-fn write_file(
-    path: impl AsRef<Utf8Path>,
-    content: impl AsRef<[u8]>,
-) -> std::io::Result<()> {
-    let path = path.as_ref();
-    AtomicFile::new(path, OverwriteBehavior::AllowOverwrite)
-        .write(|f| f.write_all(content.as_ref()))
-        .map_err(|e| e.into())
-}
+    let materializer = Materializer::standard(repo_root, &output_dir)?;
+    let result = materializer.materialize("openapi/api.json.gitstub")?;
 
-/// Returns `Ok(true)` if jj is available, `Ok(false)` if `SKIP_JJ_TESTS`
-/// is set, or `Err` if jj is not found.
-fn check_jj_available() -> Result<bool> {
-    if std::env::var("SKIP_JJ_TESTS").is_ok() {
-        return Ok(false);
-    }
+    assert!(result.exists(), "materialized file should exist");
+    let materialized_content = fs::read_to_string(&result)?;
+    assert_eq!(
+        materialized_content, r#"{"name": "test-api", "version": "1.0.0"}"#,
+        "materialized content should match original"
+    );
 
-    match jj_command().arg("--version").output() {
-        Ok(o) if o.status.success() => Ok(true),
-        Ok(o) => Err(anyhow::anyhow!(
-            "jj --version failed ({}): {}. \
-             Set SKIP_JJ_TESTS=1 to skip these tests",
-            o.status,
-            String::from_utf8_lossy(&o.stderr).trim(),
-        )),
-        Err(e) => Err(anyhow::anyhow!(
-            "jj not found ({e}). Install jj \
-             (https://jj-vcs.dev/) or set SKIP_JJ_TESTS=1 to \
-             skip these tests"
-        )),
-    }
+    assert_eq!(
+        result.file_name().unwrap(),
+        "api.json",
+        "filename should match original (without .gitstub extension)"
+    );
+
+    Ok(())
 }
 
-// ---------------------------------------------------------------------------
-// Repository setup helpers
-// ---------------------------------------------------------------------------
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_materialize_git_stub_async() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+    write_file(
+        repo_root.join("openapi").join("api.json.gitstub"),
+        format!("{commit_hash}:openapi/api.json\n"),
+    )?;
 
-/// Initializes a git repository and configures the user.
-fn init_git_repo(repo_root: &Utf8Path) -> Result<()> {
-    let status =
-        git_command().args(["init"]).current_dir(repo_root).status()?;
-    assert!(status.success(), "git init failed");
+    let output_dir = repo_root.join("out");
+    let materializer = Materializer::standard(repo_root, &output_dir)?;
+    let result =
+        materializer.materialize_async("openapi/api.json.gitstub").await?;
+    assert_eq!(result, output_dir.join("openapi").join("api.json"));
+    assert_eq!(
+        fs::read_to_string(&result)?,
+        r#"{"name": "test-api", "version": "1.0.0"}"#,
+        "materialized content should match original"
+    );
+    let result = materializer
+        .materialize_with_status_async("openapi/api.json.gitstub")
+        .await?;
+    assert!(!result.written(), "unchanged output shouldn't be rewritten");
 
-    let status = git_command()
-        .args(["config", "user.email", "test@example.com"])
-        .current_dir(repo_root)
-        .status()?;
-    assert!(status.success(), "git config user.email failed");
+    let result = materializer.materialize_async("missing.json.gitstub").await;
+    assert!(
+        matches!(result, Err(MaterializeError::ReadGitStub { .. })),
+        "should fail with ReadGitStub error, got: {result:?}"
+    );
 
-    let status = git_command()
-        .args(["config", "user.name", "Test User"])
-        .current_dir(repo_root)
-        .status()?;
-    assert!(status.success(), "git config user.name failed");
+    // Reading git stubs from a commit also works asynchronously.
+    let materializer = materializer.with_git_stub_revision("HEAD")?;
+    let result =
+        materializer.materialize_async("openapi/api.json.gitstub").await;
+    assert!(
+        matches!(result, Err(MaterializeError::ReadGitStubAtCommit { .. })),
+        "git stub isn't committed, got: {result:?}"
+    );
 
     Ok(())
 }
 
-/// Creates a JSON file and commits it via git.
-/// Returns the commit hash.
-fn commit_json_via_git(repo_root: &Utf8Path, contents: &str) -> Result<String> {
-    let json_path = repo_root.join("openapi").join("api.json");
-    fs::create_dir_all(json_path.parent().unwrap())?;
-    write_file(&json_path, contents)?;
+#[test]
+fn test_materialize_unchanged() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+    write_file(
+        repo_root.join("api.json.gitstub"),
+        format!("{commit_hash}:openapi/api.json\n"),
+    )?;
 
-    let status =
-        git_command().args(["add", "."]).current_dir(repo_root).status()?;
-    assert!(status.success(), "git add failed");
+    let output_dir = repo_root.join("out");
+    let materializer = Materializer::standard(repo_root, &output_dir)?;
+    let result = materializer.materialize_with_status("api.json.gitstub")?;
+    assert!(result.written(), "new output should be written");
+    let output_path = result.into_output_path();
 
-    let status = git_command()
-        .args(["commit", "-m", "Add API spec"])
-        .current_dir(repo_root)
-        .status()?;
-    assert!(status.success(), "git commit failed");
+    // Set the modification time to the past, so that any rewrite would be
+    // detected regardless of timestamp granularity.
+    let past = SystemTime::now() - Duration::from_secs(3600);
+    fs::File::options().write(true).open(&output_path)?.set_modified(past)?;
 
-    let output = git_command()
-        .args(["rev-parse", "HEAD"])
-        .current_dir(repo_root)
-        .output()?;
-    assert!(
-        output.status.success(),
-        "git rev-parse HEAD failed: {}",
-        String::from_utf8_lossy(&output.stderr)
+    let result = materializer.materialize_with_status("api.json.gitstub")?;
+    assert!(!result.written(), "unchanged output should not be written");
+    assert_eq!(
+        fs::metadata(&output_path)?.modified()?,
+        past,
+        "modification time should be preserved"
+    );
+
+    // The same goes for materialize and materialize_to.
+    materializer.materialize("api.json.gitstub")?;
+    materializer.materialize_to("api.json.gitstub", "api.json")?;
+    assert_eq!(fs::metadata(&output_path)?.modified()?, past);
+
+    // Modified outputs are rewritten.
+    write_file(&output_path, "modified")?;
+    let result = materializer.materialize_with_status("api.json.gitstub")?;
+    assert!(result.written(), "modified output should be rewritten");
+    assert_eq!(
+        fs::read_to_string(&output_path)?,
+        r#"{"name": "test-api", "version": "1.0.0"}"#,
     );
-    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+
+    Ok(())
 }
 
-/// Creates a JSON file and commits it via jj.
-/// Returns the commit hash.
-fn commit_json_via_jj(repo_root: &Utf8Path, contents: &str) -> Result<String> {
-    let json_path = repo_root.join("openapi").join("api.json");
-    fs::create_dir_all(json_path.parent().unwrap())?;
-    write_file(&json_path, contents)?;
+#[test]
+fn test_materialize_state_file() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+    write_file(
+        repo_root.join("api.json.gitstub"),
+        format!("{commit_hash}:openapi/api.json\n"),
+    )?;
 
-    let status = jj_command()
-        .args(["commit", "-m", "Add API spec"])
-        .current_dir(repo_root)
-        .status()?;
-    assert!(status.success(), "jj commit failed");
+    let output_dir = repo_root.join("out");
+    let materializer =
+        Materializer::standard(repo_root, &output_dir)?.with_state_file(true);
+    let result = materializer.materialize_with_status("api.json.gitstub")?;
+    assert!(result.written(), "new output should be written");
+    assert!(
+        output_dir.join(".git-stub-state").exists(),
+        "state file should be written"
+    );
 
-    let output = jj_command()
-        .args(["log", "-r", "@-", "--no-graph", "-T", "commit_id"])
-        .current_dir(repo_root)
-        .output()?;
+    // A new materializer picks up the state file. Hide the repository, so
+    // that any VCS command would fail.
+    let materializer =
+        Materializer::standard(repo_root, &output_dir)?.with_state_file(true);
+    fs::rename(repo_root.join(".git"), repo_root.join(".git-hidden"))?;
+    let result = materializer.materialize_with_status("api.json.gitstub")?;
+    assert!(!result.written(), "fresh output should not be written");
+    #[cfg(feature = "manifest")]
+    assert_eq!(
+        materializer.manifest()?.entries()[0].size(),
+        fs::metadata(result.output_path())?.len(),
+        "fresh outputs should be in the manifest"
+    );
+
+    // If the output no longer matches the state file, the VCS is run again.
+    write_file(result.output_path(), "modified")?;
+    let error = materializer
+        .materialize("api.json.gitstub")
+        .expect_err("VCS should be run for a modified output");
     assert!(
-        output.status.success(),
-        "jj log failed: {}",
-        String::from_utf8_lossy(&output.stderr)
+        matches!(error, MaterializeError::ResolveChain(_)),
+        "unexpected error: {error}"
+    );
+
+    fs::rename(repo_root.join(".git-hidden"), repo_root.join(".git"))?;
+    materializer.materialize("api.json.gitstub")?;
+    assert_eq!(
+        fs::read_to_string(result.output_path())?,
+        r#"{"name": "test-api", "version": "1.0.0"}"#,
     );
-    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+
+    // A corrupt state file is ignored.
+    write_file(output_dir.join(".git-stub-state"), "garbage")?;
+    let materializer =
+        Materializer::standard(repo_root, &output_dir)?.with_state_file(true);
+    materializer.materialize("api.json.gitstub")?;
+
+    Ok(())
 }
 
-/// Sets up a temporary git repository with a committed JSON file.
-/// Returns (temp_dir, commit_hash).
-fn setup_git_repo() -> Result<(Utf8TempDir, String)> {
-    let temp = Utf8TempDir::with_prefix("git-stub-materialize-")?;
+#[test]
+fn test_materialize_prune() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
     let repo_root = temp.path();
+    fs::create_dir(repo_root.join("old"))?;
+    for git_stub_path in ["a.json.gitstub", "old/b.json.gitstub"] {
+        write_file(
+            repo_root.join(git_stub_path),
+            format!("{commit_hash}:openapi/api.json\n"),
+        )?;
+    }
 
-    init_git_repo(repo_root)?;
-    let commit_hash = commit_json_via_git(
-        repo_root,
-        r#"{"name": "test-api", "version": "1.0.0"}"#,
-    )?;
+    let output_dir = repo_root.join("out");
+    let materializer =
+        Materializer::standard(repo_root, &output_dir)?.with_state_file(true);
+    materializer.materialize_all(["a.json.gitstub", "old/b.json.gitstub"])?;
+    assert_eq!(materializer.prune()?, Vec::<Utf8PathBuf>::new());
+
+    // In a later run, only a.json.gitstub is materialized, and a stray file
+    // has appeared.
+    fs::create_dir_all(output_dir.join("stray/nested"))?;
+    write_file(output_dir.join("stray/nested/c.txt"), "stray")?;
+    let materializer =
+        Materializer::standard(repo_root, &output_dir)?.with_state_file(true);
+    let a_path = materializer.materialize("a.json.gitstub")?;
+    let depfile_path = output_dir.join("git-stubs.d");
+    materializer.write_depfile(&depfile_path)?;
+    assert_eq!(
+        materializer.prune()?,
+        vec![
+            output_dir.join("old/b.json"),
+            output_dir.join("stray/nested/c.txt"),
+        ],
+    );
+    assert!(a_path.exists(), "materialized output should be kept");
+    assert!(depfile_path.exists(), "depfile should be kept");
+    assert!(
+        output_dir.join(".git-stub-state").exists(),
+        "state file should be kept"
+    );
+    assert!(!output_dir.join("old").exists(), "empty dirs are removed");
+    assert!(!output_dir.join("stray").exists(), "empty dirs are removed");
+    let state = fs::read_to_string(output_dir.join(".git-stub-state"))?;
+    assert!(!state.contains("b.json"), "pruned outputs are forgotten");
+
+    // Outputs of git stubs that fail to materialize are kept.
+    let materializer = Materializer::standard(repo_root, &output_dir)?;
+    let b_path = materializer.materialize("old/b.json.gitstub")?;
+    write_file(repo_root.join("old/b.json.gitstub"), "not a git stub\n")?;
+    let materializer = Materializer::standard(repo_root, &output_dir)?;
+    materializer
+        .materialize_all(["a.json.gitstub", "old/b.json.gitstub"])
+        .expect_err("invalid git stub should fail");
+    // The depfile wasn't written in this run.
+    assert_eq!(materializer.prune()?, vec![depfile_path]);
+    assert!(b_path.exists(), "output of failed git stub should be kept");
+
+    // A missing output directory has nothing to prune.
+    let materializer =
+        Materializer::standard(repo_root, repo_root.join("missing"))?;
+    assert_eq!(materializer.prune()?, Vec::<Utf8PathBuf>::new());
 
-    Ok((temp, commit_hash))
+    Ok(())
 }
 
-/// Sets up a temporary jj-colocated repository with a committed JSON
-/// file. Returns (temp_dir, commit_hash).
-fn setup_jj_colocated_repo() -> Result<(Utf8TempDir, String)> {
-    let temp = Utf8TempDir::with_prefix("git-stub-materialize-jj-")?;
+#[cfg(feature = "manifest")]
+#[test]
+fn test_materialize_manifest() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
     let repo_root = temp.path();
+    for git_stub_path in ["b.json.gitstub", "a.json.gitstub"] {
+        write_file(
+            repo_root.join(git_stub_path),
+            format!("{commit_hash}:openapi/api.json\n"),
+        )?;
+    }
 
-    let status = jj_command()
-        .args(["git", "init", "--colocate"])
-        .current_dir(repo_root)
-        .status()?;
-    assert!(status.success(), "jj git init --colocate failed");
+    let output_dir = repo_root.join("out");
+    let materializer = Materializer::standard(repo_root, &output_dir)?;
+    assert!(materializer.manifest()?.entries().is_empty());
+    materializer.materialize_all(["b.json.gitstub", "a.json.gitstub"])?;
+    materializer
+        .materialize("missing.json.gitstub")
+        .expect_err("missing git stub file should fail");
 
-    let commit_hash = commit_json_via_jj(
+    let manifest = materializer.manifest()?;
+    let blob_id = run_git(
         repo_root,
-        r#"{"name": "test-api", "version": "2.0.0"}"#,
+        &["rev-parse", &format!("{commit_hash}:openapi/api.json")],
     )?;
+    let entries = manifest.entries();
+    assert_eq!(entries.len(), 2, "failed git stubs are not included");
+    assert_eq!(entries[0].git_stub_path(), "a.json.gitstub");
+    assert_eq!(entries[0].output_path(), output_dir.join("a.json"));
+    assert_eq!(entries[1].git_stub_path(), "b.json.gitstub");
+    for entry in entries {
+        assert_eq!(entry.commit().to_string(), commit_hash);
+        assert_eq!(entry.path(), "openapi/api.json");
+        assert_eq!(entry.blob_id(), blob_id.trim());
+        assert_eq!(
+            entry.size(),
+            fs::metadata(entry.output_path())?.len(),
+            "size should match the output file"
+        );
+    }
 
-    Ok((temp, commit_hash))
+    let manifest_path = output_dir.join("manifest/git-stubs.json");
+    manifest.write_json(&manifest_path)?;
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&manifest_path)?)?;
+    assert_eq!(json["version"], git_stub_vcs::MANIFEST_VERSION);
+    assert_eq!(json["files"][0]["git_stub_path"], "a.json.gitstub");
+    assert_eq!(json["files"][0]["commit"], commit_hash.as_str());
+    assert_eq!(json["files"][0]["path"], "openapi/api.json");
+    assert_eq!(json["files"][0]["blob_id"], blob_id.trim());
+    assert_eq!(json["files"][0]["size"], entries[0].size());
+    assert_eq!(
+        json["files"][0]["output_path"],
+        output_dir.join("a.json").as_str()
+    );
+
+    Ok(())
 }
 
-/// Sets up a non-colocated jj repo (`.jj` but no `.git`).
-fn setup_jj_non_colocated_repo() -> Result<(Utf8TempDir, String)> {
-    let temp = Utf8TempDir::with_prefix("git-stub-materialize-jj-noncoloc-")?;
+#[test]
+fn test_materialize_depfile() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
     let repo_root = temp.path();
+    for git_stub_path in ["b.json.gitstub", "a $#.json.gitstub"] {
+        write_file(
+            repo_root.join(git_stub_path),
+            format!("{commit_hash}:openapi/api.json\n"),
+        )?;
+    }
 
-    let status = jj_command()
-        .args(["git", "init", "--no-colocate"])
-        .current_dir(repo_root)
-        .status()?;
-    assert!(status.success(), "jj git init --no-colocate failed");
+    let output_dir = repo_root.join("out");
+    let materializer = Materializer::standard(repo_root, &output_dir)?;
+    materializer.materialize_all(["b.json.gitstub", "a $#.json.gitstub"])?;
 
-    let commit_hash = commit_json_via_jj(
-        repo_root,
-        r#"{"name": "test-api", "version": "3.0.0"}"#,
+    let depfile_path = output_dir.join("deps/git-stubs.d");
+    materializer.write_depfile(&depfile_path)?;
+    assert_eq!(
+        fs::read_to_string(&depfile_path)?,
+        format!(
+            "{repo_root}/out/a\\ $$\\#.json: \
+             {repo_root}/a\\ $$\\#.json.gitstub\n\
+             {repo_root}/out/b.json: {repo_root}/b.json.gitstub\n"
+        ),
+    );
+
+    // Git stub files read from a revision have no dependencies.
+    run_git(repo_root, &["add", "."])?;
+    run_git(repo_root, &["commit", "-m", "add git stubs for depfile"])?;
+    let materializer = Materializer::standard(repo_root, &output_dir)?
+        .with_git_stub_revision("HEAD")?;
+    materializer.materialize("b.json.gitstub")?;
+    materializer.write_depfile(&depfile_path)?;
+    assert_eq!(
+        fs::read_to_string(&depfile_path)?,
+        format!("{repo_root}/out/b.json:\n"),
+    );
+
+    Ok(())
+}
+
+// Backslashes and colons can't appear in file names on Windows.
+#[cfg(unix)]
+#[test]
+fn test_materialize_depfile_escaping() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+    write_file(
+        repo_root.join("a.json.gitstub"),
+        format!("{commit_hash}:openapi/api.json\n"),
     )?;
+    let output_dir = repo_root.join("out");
+    let depfile_path = repo_root.join("git-stubs.d");
+
+    // Backslashes are doubled before an escaped space or `#`, and left
+    // alone elsewhere.
+    let materializer = Materializer::standard(repo_root, &output_dir)?;
+    for output_path in ["a\\ b.json", "c\\\\#d.json", "e\\f.json"] {
+        materializer.materialize_to("a.json.gitstub", output_path)?;
+    }
+    materializer.write_depfile(&depfile_path)?;
+    assert_eq!(
+        fs::read_to_string(&depfile_path)?,
+        format!(
+            "{repo_root}/out/a\\\\\\ b.json: {repo_root}/a.json.gitstub\n\
+             {repo_root}/out/c\\\\\\\\\\#d.json: {repo_root}/a.json.gitstub\n\
+             {repo_root}/out/e\\f.json: {repo_root}/a.json.gitstub\n"
+        ),
+    );
+
+    // Colons, trailing backslashes and newlines can't be represented.
+    for output_path in ["a:b.json", "a.json\\", "a\nb.json"] {
+        let materializer = Materializer::standard(repo_root, &output_dir)?;
+        materializer.materialize_to("a.json.gitstub", output_path)?;
+        let result = materializer.write_depfile(&depfile_path);
+        assert!(
+            matches!(result, Err(WriteDepfileError::UnsupportedPath { .. })),
+            "{output_path:?} should be rejected, got: {result:?}"
+        );
+    }
 
-    Ok((temp, commit_hash))
+    Ok(())
 }
 
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
+#[test]
+fn test_materialize_directive_sink() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+    for git_stub_path in ["a.json.gitstub", "b.json.gitstub"] {
+        write_file(
+            repo_root.join(git_stub_path),
+            format!("{commit_hash}:openapi/api.json\n"),
+        )?;
+    }
+
+    let output_dir = repo_root.join("out");
+    let sink = DirectiveSink::collector();
+    let materializer = Materializer::standard(repo_root, &output_dir)?
+        .with_directive_sink(sink.clone());
+    materializer.materialize("a.json.gitstub")?;
+    materializer.materialize("b.json.gitstub")?;
+    assert_eq!(
+        sink.collected(),
+        [
+            "cargo::rerun-if-env-changed=GIT".to_owned(),
+            "cargo::rerun-if-env-changed=JJ".to_owned(),
+            format!("cargo::rerun-if-changed={repo_root}/a.json.gitstub"),
+            format!("cargo::rerun-if-changed={repo_root}/b.json.gitstub"),
+        ],
+        "environment variables should only be emitted once"
+    );
+
+    // Directives can be written to any writer.
+    let directives_path = repo_root.join("directives.txt");
+    let materializer = materializer.with_directive_sink(DirectiveSink::writer(
+        fs::File::create(&directives_path)?,
+    ));
+    materializer.materialize("a.json.gitstub")?;
+    assert_eq!(
+        fs::read_to_string(&directives_path)?,
+        format!(
+            "cargo::rerun-if-env-changed=GIT\n\
+             cargo::rerun-if-env-changed=JJ\n\
+             cargo::rerun-if-changed={repo_root}/a.json.gitstub\n"
+        ),
+    );
+
+    // Git stub files read from a revision can't change, so only environment
+    // variables are emitted.
+    run_git(repo_root, &["add", "a.json.gitstub"])?;
+    run_git(repo_root, &["commit", "-m", "add git stub for directives"])?;
+    let sink = DirectiveSink::collector();
+    let materializer = Materializer::standard(repo_root, &output_dir)?
+        .with_git_stub_revision("HEAD")?
+        .with_directive_sink(sink.clone());
+    materializer.materialize("a.json.gitstub")?;
+    assert_eq!(
+        sink.collected(),
+        ["cargo::rerun-if-env-changed=GIT", "cargo::rerun-if-env-changed=JJ"],
+    );
+
+    Ok(())
+}
 
 #[test]
-fn test_materialize_git_stub() -> Result<()> {
+fn test_materialize_warnings() -> Result<()> {
     let (temp, commit_hash) = setup_git_repo()?;
     let repo_root = temp.path();
 
-    let git_stub_content = format!("{}:openapi/api.json\n", commit_hash);
-    let git_stub_path = repo_root.join("openapi").join("api.json.gitstub");
-    write_file(&git_stub_path, &git_stub_content)?;
+    // Create a commit that isn't reachable from HEAD.
+    run_git(repo_root, &["checkout", "-b", "side"])?;
+    let side_hash = commit_json_via_git(repo_root, r#"{"side": true}"#)?;
+    run_git(repo_root, &["checkout", "-"])?;
+
+    run_git(repo_root, &["rm", "--quiet", "openapi/api.json"])?;
+    write_file(
+        repo_root.join("canonical.json.gitstub"),
+        format!("{commit_hash}:openapi/api.json\n"),
+    )?;
+    write_file(
+        repo_root.join("non-canonical.json.gitstub"),
+        format!("{commit_hash}:openapi/api.json"),
+    )?;
+    write_file(
+        repo_root.join("unreachable.json.gitstub"),
+        format!("{side_hash}:openapi/api.json\n"),
+    )?;
 
     let output_dir = repo_root.join("out");
-    fs::create_dir_all(&output_dir)?;
+    let sink = DirectiveSink::collector();
+    let materializer = Materializer::standard(repo_root, &output_dir)?
+        .with_directive_sink(sink.clone())
+        .with_warnings(true);
+    let warnings = |git_stub_path| -> Result<Vec<String>> {
+        let before = sink.collected().len();
+        materializer.materialize(git_stub_path)?;
+        Ok(sink.collected()[before..]
+            .iter()
+            .filter_map(|d| d.strip_prefix("cargo::warning=").map(String::from))
+            .collect())
+    };
+
+    assert_eq!(warnings("canonical.json.gitstub")?, Vec::<String>::new());
+    assert_eq!(
+        warnings("non-canonical.json.gitstub")?,
+        [format!(
+            "git stub {repo_root}/non-canonical.json.gitstub is not in \
+             canonical form (it should contain \
+             `{commit_hash}:openapi/api.json` followed by a newline)"
+        )],
+    );
+    assert_eq!(
+        warnings("unreachable.json.gitstub")?,
+        [format!(
+            "git stub {repo_root}/unreachable.json.gitstub references commit \
+             {side_hash}, which is not reachable from HEAD and may be lost \
+             to garbage collection"
+        )],
+    );
 
-    let materializer = Materializer::standard(repo_root, &output_dir)?;
-    let result = materializer.materialize("openapi/api.json.gitstub")?;
+    // An untracked file at the referenced path, such as a generated file,
+    // is fine.
+    fs::create_dir(repo_root.join("openapi"))?;
+    write_file(repo_root.join("openapi/api.json"), "generated")?;
+    assert_eq!(warnings("canonical.json.gitstub")?, Vec::<String>::new());
 
-    assert!(result.exists(), "materialized file should exist");
-    let materialized_content = fs::read_to_string(&result)?;
+    // Restore the referenced file to the index.
+    run_git(repo_root, &["checkout", "HEAD", "--", "openapi/api.json"])?;
     assert_eq!(
-        materialized_content, r#"{"name": "test-api", "version": "1.0.0"}"#,
-        "materialized content should match original"
+        warnings("canonical.json.gitstub")?,
+        [format!(
+            "git stub {repo_root}/canonical.json.gitstub references \
+             openapi/api.json, which is also tracked in the working tree"
+        )],
     );
 
+    // With the state file, checks that run the VCS are skipped for fresh
+    // outputs, but the canonical form is still checked.
+    let materializer = Materializer::standard(repo_root, &output_dir)?
+        .with_state_file(true)
+        .with_directive_sink(sink.clone())
+        .with_warnings(true);
+    materializer.materialize("canonical.json.gitstub")?;
+    materializer.materialize("non-canonical.json.gitstub")?;
+    let before = sink.collected().len();
+    for git_stub_path in
+        ["canonical.json.gitstub", "non-canonical.json.gitstub"]
+    {
+        assert!(
+            !materializer.materialize_with_status(git_stub_path)?.written()
+        );
+    }
+    let fresh_warnings: Vec<_> = sink.collected()[before..]
+        .iter()
+        .filter_map(|d| d.strip_prefix("cargo::warning=").map(String::from))
+        .collect();
     assert_eq!(
-        result.file_name().unwrap(),
-        "api.json",
-        "filename should match original (without .gitstub extension)"
+        fresh_warnings,
+        [format!(
+            "git stub {repo_root}/non-canonical.json.gitstub is not in \
+             canonical form (it should contain \
+             `{commit_hash}:openapi/api.json` followed by a newline)"
+        )],
+    );
+
+    // Warnings are disabled by default outside build scripts.
+    let sink = DirectiveSink::collector();
+    Materializer::standard(repo_root, &output_dir)?
+        .with_directive_sink(sink.clone())
+        .materialize("non-canonical.json.gitstub")?;
+    assert!(
+        !sink.collected().iter().any(|d| d.starts_with("cargo::warning=")),
+        "no warnings expected: {:?}",
+        sink.collected()
     );
 
     Ok(())
 }
 
+#[test]
+fn test_materialize_rust_module() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+    fs::create_dir(repo_root.join("specs"))?;
+    for git_stub_path in ["specs/api-v1.json.gitstub", "2.bin.gitstub"] {
+        write_file(
+            repo_root.join(git_stub_path),
+            format!("{commit_hash}:openapi/api.json\n"),
+        )?;
+    }
+
+    let output_dir = repo_root.join("out");
+    let sink = DirectiveSink::collector();
+    let materializer = Materializer::standard(repo_root, &output_dir)?
+        .with_directive_sink(sink.clone());
+    materializer
+        .materialize_all(["specs/api-v1.json.gitstub", "2.bin.gitstub"])?;
+
+    let module_path = output_dir.join("git_stubs.rs");
+    materializer.write_rust_module(&module_path)?;
+    let spec_path = output_dir.join("specs/api-v1.json");
+    let bin_path = output_dir.join("2.bin");
+    assert_eq!(
+        fs::read_to_string(&module_path)?,
+        format!(
+            "// @generated by git-stub-vcs. Do not edit.\n\
+             //\n\
+             // Paths to, and contents of, files materialized from git stubs.\n\
+             \n\
+             /// The path to the file materialized from \
+             `specs/api-v1.json.gitstub`.\n\
+             pub const SPECS_API_V1_JSON_PATH: &str = {spec_path:?};\n\
+             /// The contents of the file materialized from \
+             `specs/api-v1.json.gitstub`.\n\
+             pub const SPECS_API_V1_JSON: &[u8] = \
+             include_bytes!({spec_path:?});\n\
+             \n\
+             /// The path to the file materialized from `2.bin.gitstub`.\n\
+             pub const _2_BIN_PATH: &str = {bin_path:?};\n\
+             /// The contents of the file materialized from `2.bin.gitstub`.\n\
+             pub const _2_BIN: &[u8] = include_bytes!({bin_path:?});\n",
+            bin_path = bin_path.as_str(),
+            spec_path = spec_path.as_str(),
+        ),
+    );
+    let rustc_env: Vec<_> = sink
+        .collected()
+        .into_iter()
+        .filter(|d| d.starts_with("cargo::rustc-env="))
+        .collect();
+    assert_eq!(
+        rustc_env,
+        [
+            format!("cargo::rustc-env=GIT_STUB_SPECS_API_V1_JSON={spec_path}"),
+            format!("cargo::rustc-env=GIT_STUB__2_BIN={bin_path}"),
+        ],
+    );
+
+    // Git stubs whose names collide are rejected.
+    write_file(
+        repo_root.join("specs_api-v1.json.gitstub"),
+        format!("{commit_hash}:openapi/api.json\n"),
+    )?;
+    materializer.materialize("specs_api-v1.json.gitstub")?;
+    let error = materializer
+        .write_rust_module(&module_path)
+        .expect_err("colliding names should fail");
+    assert!(
+        matches!(
+            &error,
+            WriteModuleError::DuplicateName { name, git_stub_paths }
+                if name == "SPECS_API_V1_JSON" && git_stub_paths.len() == 2
+        ),
+        "unexpected error: {error}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_materialize_all() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+    let git_stub_paths: Vec<String> =
+        (0..8).map(|i| format!("openapi/api-{i}.json.gitstub")).collect();
+    for git_stub_path in &git_stub_paths {
+        write_file(
+            repo_root.join(git_stub_path),
+            format!("{commit_hash}:openapi/api.json\n"),
+        )?;
+    }
+
+    let output_dir = repo_root.join("out");
+    let materializer = Materializer::standard(repo_root, &output_dir)?
+        .with_max_concurrency(NonZeroUsize::new(3).unwrap());
+    let results = materializer.materialize_all(&git_stub_paths)?;
+    assert_eq!(results.len(), git_stub_paths.len());
+    for (i, result) in results.iter().enumerate() {
+        assert_eq!(
+            result.output_path(),
+            output_dir.join(format!("openapi/api-{i}.json")),
+            "results should be in input order"
+        );
+        assert!(result.written(), "output should be written");
+        assert_eq!(
+            fs::read_to_string(result.output_path())?,
+            r#"{"name": "test-api", "version": "1.0.0"}"#,
+        );
+    }
+
+    // Errors are reported in input order, and don't stop other git stubs
+    // from being materialized.
+    fs::remove_dir_all(&output_dir)?;
+    write_file(repo_root.join("openapi/api-5.json.gitstub"), "invalid\n")?;
+    let mut paths = git_stub_paths.clone();
+    paths.insert(2, "missing.json.gitstub".to_owned());
+    paths.push("not-a-stub.json".to_owned());
+    let error = materializer.materialize_all(&paths).unwrap_err();
+    let MaterializeAllError::Failed { errors } = &error else {
+        panic!("expected Failed error, got: {error:?}");
+    };
+    let failed: Vec<_> = errors.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(
+        failed,
+        [
+            "missing.json.gitstub",
+            "openapi/api-5.json.gitstub",
+            "not-a-stub.json"
+        ],
+    );
+    assert!(
+        matches!(errors[0].1, MaterializeError::ReadGitStub { .. }),
+        "unexpected error: {:?}",
+        errors[0].1
+    );
+    assert!(
+        matches!(errors[1].1, MaterializeError::InvalidGitStub { .. }),
+        "unexpected error: {:?}",
+        errors[1].1
+    );
+    assert!(
+        matches!(errors[2].1, MaterializeError::NotGitStub { .. }),
+        "unexpected error: {:?}",
+        errors[2].1
+    );
+    assert!(output_dir.join("openapi/api-7.json").exists());
+
+    // Duplicate output paths are rejected before anything is materialized.
+    fs::remove_dir_all(&output_dir)?;
+    let error = materializer
+        .materialize_all([
+            "openapi/api-0.json.gitstub",
+            "openapi/api-0.json.gitstub",
+        ])
+        .unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            MaterializeAllError::DuplicateOutput { output_path, git_stub_paths }
+                if output_path == &output_dir.join("openapi/api-0.json")
+                    && git_stub_paths.len() == 2
+        ),
+        "unexpected error: {error:?}"
+    );
+    assert!(!output_dir.exists(), "nothing should be materialized");
+
+    Ok(())
+}
+
 #[test]
 fn test_materialize_missing_git_stub() -> Result<()> {
     let (temp, _) = setup_git_repo()?;
@@ -291,6 +814,209 @@ fn test_vcs_detection_with_jj_dir() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_vcs_discovery() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+    let crate_dir = repo_root.join("crates").join("foo");
+    fs::create_dir_all(&crate_dir)?;
+
+    let (vcs, discovered) = Vcs::discover(&crate_dir)?;
+    assert_eq!(vcs.name(), VcsName::Git, "should detect git");
+    assert_eq!(discovered, repo_root, "should find the repository root");
+
+    // `..` components are resolved before walking up.
+    let (_, discovered) = Vcs::discover(&crate_dir.join("../foo/.."))?;
+    assert_eq!(discovered, repo_root);
+
+    // Discovering from inside the .git directory finds the working tree,
+    // not the Git directory itself.
+    let (vcs, discovered) = Vcs::discover(&repo_root.join(".git/refs/heads"))?;
+    assert_eq!(vcs.name(), VcsName::Git);
+    assert_eq!(discovered, repo_root, "should not stop at the Git directory");
+
+    let result = Vcs::discover(&crate_dir.join("missing"));
+    assert!(
+        matches!(result, Err(VcsDetectError::PathNotFound { .. })),
+        "missing start directory should be an error, got: {result:?}"
+    );
+
+    write_file(
+        repo_root.join("openapi").join("api.json.gitstub"),
+        format!("{commit_hash}:openapi/api.json\n"),
+    )?;
+    let output_dir = repo_root.join("out");
+    let materializer = Materializer::discover(&crate_dir, &output_dir)?;
+    assert_eq!(materializer.repo_root(), repo_root);
+    let result = materializer.materialize("openapi/api.json.gitstub")?;
+    assert_eq!(
+        fs::read_to_string(&result)?,
+        r#"{"name": "test-api", "version": "1.0.0"}"#,
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_vcs_detection_git_worktree() -> Result<()> {
+    let (temp, _) = setup_git_repo()?;
+    let repo_root = temp.path().join("main");
+    fs::create_dir(&repo_root)?;
+    for entry in [".git", "openapi"] {
+        fs::rename(temp.path().join(entry), repo_root.join(entry))?;
+    }
+    let worktree = temp.path().join("worktree");
+    let status = git_command()
+        .args(["worktree", "add", "--detach", worktree.as_str()])
+        .current_dir(&repo_root)
+        .status()?;
+    assert!(status.success(), "git worktree add failed");
+
+    let vcs = Vcs::detect(&worktree)?;
+    assert_eq!(vcs.name(), VcsName::Git, "worktrees should be detected");
+
+    // Simulate a pruned worktree.
+    fs::remove_dir_all(repo_root.join(".git").join("worktrees"))?;
+    let result = Vcs::detect(&worktree);
+    assert!(
+        matches!(result, Err(VcsDetectError::DanglingGitDir { .. })),
+        "stale worktree should be an error, got: {result:?}"
+    );
+
+    write_file(worktree.join(".git"), "not a gitfile\n")?;
+    let result = Vcs::detect(&worktree);
+    assert!(
+        matches!(result, Err(VcsDetectError::InvalidGitFile { .. })),
+        "invalid .git file should be an error, got: {result:?}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_materialize_bare_repo() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path().join("repo");
+    fs::create_dir(&repo_root)?;
+    for entry in [".git", "openapi"] {
+        fs::rename(temp.path().join(entry), repo_root.join(entry))?;
+    }
+    write_file(
+        repo_root.join("api.json.gitstub"),
+        format!("{commit_hash}:openapi/api.json\n"),
+    )?;
+    run_git(&repo_root, &["add", "api.json.gitstub"])?;
+    run_git(&repo_root, &["commit", "-m", "Add git stub"])?;
+
+    let bare = temp.path().join("bare.git");
+    run_git(temp.path(), &["clone", "--bare", "repo", "bare.git"])?;
+    let vcs = Vcs::detect(&bare)?;
+    assert_eq!(vcs.name(), VcsName::Git, "bare repos should be detected");
+
+    // Both a bare repository and the .git directory of a non-bare
+    // repository can be used as an explicit Git directory.
+    let head = run_git(&repo_root, &["rev-parse", "HEAD"])?;
+    for git_dir in [bare.clone(), repo_root.join(".git")] {
+        let output_dir = temp.path().join("out");
+        let materializer = Materializer::bare(&git_dir, "HEAD", &output_dir)?;
+        assert_eq!(
+            materializer.git_stub_commit().map(|c| c.to_string()),
+            Some(head.clone()),
+            "HEAD should be resolved for {git_dir}",
+        );
+        let result = materializer.materialize("api.json.gitstub")?;
+        assert_eq!(
+            fs::read_to_string(&result)?,
+            r#"{"name": "test-api", "version": "1.0.0"}"#,
+            "materialized content should match for {git_dir}",
+        );
+    }
+
+    let result = Materializer::bare(&bare, "nonexistent", temp.path());
+    assert!(
+        matches!(result, Err(MaterializeError::RevisionNotFound { .. })),
+        "nonexistent revision should be an error, got: {result:?}"
+    );
+
+    let materializer = Materializer::bare(&bare, "HEAD", temp.path())?;
+    let result = materializer.materialize("missing.json.gitstub");
+    assert!(
+        matches!(result, Err(MaterializeError::ReadGitStubAtCommit { .. })),
+        "git stub missing at commit should be an error, got: {result:?}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_materialize_git_stub_revision() -> Result<()> {
+    let (temp, v1) = setup_git_repo()?;
+    let repo_root = temp.path();
+    let v2 = commit_json_via_git(repo_root, r#"{"version": "2.0.0"}"#)?;
+
+    let git_stub_path = repo_root.join("api.json.gitstub");
+    write_file(&git_stub_path, format!("{v1}:openapi/api.json\n"))?;
+    run_git(repo_root, &["add", "api.json.gitstub"])?;
+    run_git(repo_root, &["commit", "-m", "Add git stub"])?;
+    run_git(repo_root, &["tag", "release-1"])?;
+
+    // Change the git stub after the release, then check out an unrelated
+    // state of the working tree.
+    write_file(&git_stub_path, format!("{v2}:openapi/api.json\n"))?;
+    run_git(repo_root, &["commit", "-am", "Update git stub"])?;
+    write_file(&git_stub_path, "not a valid gitstub\n")?;
+
+    let output_dir = temp.path().join("out");
+    let materializer = Materializer::standard(repo_root, &output_dir)?
+        .with_git_stub_revision("release-1")?;
+    let result = materializer.materialize("api.json.gitstub")?;
+    assert_eq!(
+        fs::read_to_string(&result)?,
+        r#"{"name": "test-api", "version": "1.0.0"}"#,
+        "git stub should be read from the tag"
+    );
+
+    let materializer = materializer.with_git_stub_revision("HEAD")?;
+    let result = materializer.materialize("api.json.gitstub")?;
+    assert_eq!(
+        fs::read_to_string(&result)?,
+        r#"{"version": "2.0.0"}"#,
+        "git stub should be read from HEAD, not the working tree"
+    );
+
+    let result = Materializer::standard(repo_root, &output_dir)?
+        .with_git_stub_revision("release-2");
+    assert!(
+        matches!(result, Err(MaterializeError::RevisionNotFound { .. })),
+        "nonexistent revision should be an error, got: {result:?}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_vcs_detection_jj_secondary_workspace() -> Result<()> {
+    // Detection doesn't run jj, so this doesn't need jj to be installed.
+    let temp = Utf8TempDir::with_prefix("git-stub-materialize-")?;
+    let main_repo = temp.path().join("main").join(".jj").join("repo");
+    fs::create_dir_all(&main_repo)?;
+    let workspace = temp.path().join("workspace");
+    fs::create_dir_all(workspace.join(".jj"))?;
+    write_file(workspace.join(".jj").join("repo"), "../../main/.jj/repo")?;
+
+    let vcs = Vcs::detect(&workspace)?;
+    assert_eq!(vcs.name(), VcsName::Jj, "secondary workspace detected");
+
+    fs::remove_dir_all(temp.path().join("main"))?;
+    let result = Vcs::detect(&workspace);
+    assert!(
+        matches!(result, Err(VcsDetectError::DanglingJjRepo { .. })),
+        "stale workspace should be an error, got: {result:?}"
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_vcs_detection_no_repo() -> Result<()> {
     let temp = Utf8TempDir::with_prefix("git-stub-materialize-")?;
@@ -618,6 +1344,55 @@ fn test_materialize_idempotent() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_materialize_git_stub_chain() -> Result<()> {
+    let (temp, commit_hash) = setup_git_repo()?;
+    let repo_root = temp.path();
+
+    // Commit a git stub, then archive it by referencing it from another git
+    // stub.
+    let inner_path = repo_root.join("openapi").join("api-v1.json.gitstub");
+    write_file(&inner_path, format!("{commit_hash}:openapi/api.json\n"))?;
+    let inner_commit = commit_json_via_git(repo_root, "{}")?;
+    let outer_path = repo_root.join("archive").join("api-v1.json.gitstub");
+    fs::create_dir_all(outer_path.parent().unwrap())?;
+    write_file(
+        &outer_path,
+        format!("{inner_commit}:openapi/api-v1.json.gitstub\n"),
+    )?;
+
+    let output_dir = repo_root.join("out");
+    let materializer = Materializer::standard(repo_root, &output_dir)?;
+    let result = materializer.materialize("archive/api-v1.json.gitstub")?;
+    assert_eq!(
+        fs::read_to_string(&result)?,
+        r#"{"name": "test-api", "version": "1.0.0"}"#,
+        "chain should be followed to the final contents"
+    );
+
+    let vcs = Vcs::git()?;
+    let outer: GitStub = fs::read_to_string(&outer_path)?.parse()?;
+    let chain = vcs.read_git_stub_chain(&outer, repo_root, 1)?;
+    assert_eq!(chain.stubs().len(), 2);
+    assert_eq!(chain.stubs()[1].commit().to_string(), commit_hash);
+
+    let result = materializer
+        .with_max_chain_depth(0)
+        .materialize("archive/api-v1.json.gitstub");
+    assert!(
+        matches!(
+            result,
+            Err(MaterializeError::ResolveChain(ResolveChainError::TooDeep {
+                max_depth: 0,
+                ..
+            }))
+        ),
+        "chains should be rejected with a maximum depth of 0, got: {result:?}"
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_materialize_git_stub_with_jj_dash_prefixed_path() -> Result<()> {
     if !check_jj_available()? {
@@ -0,0 +1,114 @@
+// Copyright 2026 Oxide Computer Company
+
+//! Integration tests for re-pinning git stubs.
+
+use crate::helpers::{
+    commit_json_via_git, run_git, setup_git_repo, write_file,
+};
+use anyhow::Result;
+use git_stub::GitCommitHash;
+use git_stub_vcs::{CommitMap, CommitMapParseError, Vcs};
+
+const V2_CONTENTS: &str = r#"{"name": "test-api", "version": "2.0.0"}"#;
+
+#[test]
+fn test_repin_after_squash_merge() -> Result<()> {
+    let (temp, _) = setup_git_repo()?;
+    let repo_root = temp.path();
+
+    // Make a change on a feature branch, then "squash merge" it by making an
+    // identical change on the main branch.
+    run_git(repo_root, &["checkout", "-b", "feature"])?;
+    let feature = commit_json_via_git(repo_root, V2_CONTENTS)?;
+    run_git(repo_root, &["checkout", "-"])?;
+    write_file(repo_root.join("openapi/api.json"), V2_CONTENTS)?;
+    run_git(repo_root, &["commit", "-am", "Squash merge feature"])?;
+    let squashed = run_git(repo_root, &["rev-parse", "HEAD"])?;
+    write_file(
+        repo_root.join("openapi/api.json"),
+        r#"{"name": "test-api", "version": "3.0.0"}"#,
+    )?;
+    run_git(repo_root, &["commit", "-am", "Update API spec again"])?;
+
+    let missing = "dead".repeat(10);
+    write_file(
+        repo_root.join("openapi/api-v2.json.gitstub"),
+        format!("{feature}:openapi/api.json\n"),
+    )?;
+    write_file(
+        repo_root.join("openapi/api-missing.json.gitstub"),
+        format!("{missing}:openapi/api.json\n"),
+    )?;
+
+    let vcs = Vcs::git()?;
+    let repins = vcs.repin_git_stubs(repo_root, "HEAD", None)?;
+    let summary: Vec<_> = repins
+        .iter()
+        .map(|repin| {
+            (
+                repin.stub_file().path().as_str(),
+                repin.new_stub().map(|stub| stub.to_file_contents()),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("openapi/api-missing.json.gitstub", None),
+            (
+                "openapi/api-v2.json.gitstub",
+                Some(format!("{squashed}:openapi/api.json\n")),
+            ),
+        ]
+    );
+
+    // With a commit map, the missing commit can be translated.
+    let commit_map: CommitMap =
+        format!("old new\n{missing} {squashed}\n").parse()?;
+    let repins = vcs.repin_git_stubs(repo_root, "HEAD", Some(&commit_map))?;
+    let new_stub = repins[0].new_stub().expect("mapped commit is used");
+    assert_eq!(new_stub.commit().to_string(), squashed);
+
+    // Reachable git stubs are left alone.
+    let feature_hash: GitCommitHash = feature.parse()?;
+    assert!(!vcs.is_ancestor(repo_root, feature_hash, "HEAD")?);
+    assert_eq!(vcs.repin_git_stubs(repo_root, "feature", None)?.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_commit_map_parse() -> Result<()> {
+    let old = "ab".repeat(20);
+    let new = "cd".repeat(20);
+    let removed = "ef".repeat(20);
+    let commit_map: CommitMap = format!(
+        "old                                      new\n\
+         {old} {new}\n\
+         {removed} {}\n",
+        "0".repeat(40),
+    )
+    .parse()?;
+    assert_eq!(commit_map.get(old.parse()?), Some(new.parse()?));
+    assert_eq!(commit_map.get(removed.parse()?), None, "removed commit");
+    assert_eq!(commit_map.get(new.parse()?), None, "unknown commit");
+
+    let result = format!("{old}\n").parse::<CommitMap>();
+    assert!(
+        matches!(
+            result,
+            Err(CommitMapParseError::InvalidLine { line_number: 1, .. })
+        ),
+        "single hash should be rejected, got: {result:?}"
+    );
+    let result = format!("{old} {new}\n{old} xyz\n").parse::<CommitMap>();
+    assert!(
+        matches!(
+            result,
+            Err(CommitMapParseError::InvalidHash { line_number: 2, .. })
+        ),
+        "invalid hash should be rejected, got: {result:?}"
+    );
+
+    Ok(())
+}